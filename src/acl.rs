@@ -0,0 +1,121 @@
+use std::str::FromStr;
+
+use crate::http::HttpMethod;
+use crate::{Error, Result};
+
+/// Coarse method + path prefix authorization, evaluated before any handler runs.
+/// A request matching an allow rule is always accepted, otherwise it is rejected
+/// if it matches a deny rule.
+/// CONNECT rules match the authority of the tunnel instead of a path prefix, and a
+/// CONNECT is rejected unless it matches an allow rule so that enabling tunnels does not
+/// make an open proxy.
+#[derive(Debug, Default, Clone)]
+pub struct Acl {
+    pub allow: Vec<(HttpMethod, String)>,
+    pub deny: Vec<(HttpMethod, String)>,
+}
+
+impl Acl {
+    /// rule is formatted as METHOD:/path/prefix, for ex POST:/files/, or as
+    /// CONNECT:host[:port], for ex CONNECT:example.com:443. Without port, all the ports of
+    /// the host match
+    pub fn parse_rule(rule: &str) -> Result<(HttpMethod, String)> {
+        let (method, target) = rule
+            .split_once(':')
+            .ok_or_else(|| Error::InvalidAclRule(rule.to_string()))?;
+        let method =
+            HttpMethod::from_str(method).map_err(|_| Error::InvalidAclRule(rule.to_string()))?;
+        let valid = match method {
+            HttpMethod::Connect => !target.is_empty() && !target.contains('/'),
+            _ => target.starts_with('/'),
+        };
+        if !valid {
+            Err(Error::InvalidAclRule(rule.to_string()))?;
+        }
+        Ok((method, target.to_string()))
+    }
+
+    /// path is the authority of a CONNECT request
+    pub fn is_allowed(&self, method: &HttpMethod, path: &str) -> bool {
+        let matches = |rules: &[(HttpMethod, String)]| {
+            rules.iter().any(|(m, target)| {
+                m == method
+                    && match method {
+                        HttpMethod::Connect => authority_matches(target, path),
+                        _ => path.starts_with(target.as_str()),
+                    }
+            })
+        };
+        match method {
+            HttpMethod::Connect => matches(&self.allow),
+            _ => matches(&self.allow) || !matches(&self.deny),
+        }
+    }
+}
+
+/// Host and port of an authority, the port is None if there is none. The host of an
+/// ipv6 address is in brackets, for ex [::1]:443
+fn split_port(authority: &str) -> (&str, Option<&str>) {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|x| x.is_ascii_digit()) => {
+            (host, Some(port))
+        }
+        _ => (authority, None),
+    }
+}
+
+/// Hosts are case insensitive, a rule without port matches any port
+fn authority_matches(rule: &str, authority: &str) -> bool {
+    let (rule_host, rule_port) = split_port(rule);
+    let (host, port) = split_port(authority);
+    rule_host.eq_ignore_ascii_case(host) && (rule_port.is_none() || rule_port == port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(allow: &[&str], deny: &[&str]) -> Acl {
+        let rules = |rules: &[&str]| rules.iter().map(|x| Acl::parse_rule(x).unwrap()).collect();
+        Acl {
+            allow: rules(allow),
+            deny: rules(deny),
+        }
+    }
+
+    #[test]
+    fn connect_is_only_allowed_to_the_authorities_of_allow_rules() {
+        let acl = acl(&["CONNECT:example.com:443", "CONNECT:[::1]"], &[]);
+        assert!(acl.is_allowed(&HttpMethod::Connect, "example.com:443"));
+        assert!(acl.is_allowed(&HttpMethod::Connect, "EXAMPLE.com:443"));
+        assert!(acl.is_allowed(&HttpMethod::Connect, "[::1]:8080"));
+        for authority in [
+            "example.com:22",
+            "example.com.evil.com:443",
+            "localhost:443",
+        ] {
+            assert!(
+                !acl.is_allowed(&HttpMethod::Connect, authority),
+                "{}",
+                authority
+            );
+        }
+        // NOTE: other methods are still allowed by default
+        assert!(acl.is_allowed(&HttpMethod::Get, "/files/a.txt"));
+        assert!(!Acl::default().is_allowed(&HttpMethod::Connect, "example.com:443"));
+    }
+
+    #[test]
+    fn connect_rules_are_authorities_and_other_rules_paths() {
+        assert!(Acl::parse_rule("CONNECT:example.com:443").is_ok());
+        assert!(Acl::parse_rule("POST:/files/").is_ok());
+        for rule in [
+            "CONNECT:/files/",
+            "CONNECT:",
+            "POST:example.com:443",
+            "/files/",
+        ] {
+            assert!(Acl::parse_rule(rule).is_err(), "{}", rule);
+        }
+    }
+}
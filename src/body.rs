@@ -0,0 +1,614 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    sync::Arc,
+    time::Instant,
+};
+
+use crate::http::HttpHeader;
+use crate::listener::Stream;
+use crate::Error;
+
+/// Maximum length of a chunk size line, extensions included
+const MAX_CHUNK_LINE_LENGTH: u64 = 1024;
+
+#[derive(Debug)]
+enum Framing {
+    /// Content-Length body, holds the number of bytes left to read
+    Length(u64),
+    /// Transfer-Encoding: chunked body
+    Chunked { remaining_in_chunk: u64, done: bool },
+}
+
+/// Reads the body of a request directly from the connection, so that it does not have
+/// to be buffered in memory. It stops at the end of the body, respecting the framing.
+#[derive(Debug)]
+pub struct BodyReader<'a> {
+    reader: &'a mut BufReader<Stream>,
+    framing: Framing,
+    /// 100 Continue is only sent once the body is actually read. That way, a request
+    /// rejected before reading its body does not make the client send it
+    expect_continue: bool,
+    max_body_size: usize,
+    read: u64,
+    /// once the body is known to be invalid, the stream can not be read further
+    failed: bool,
+    /// the whole request must be received before, see ParseOptions::request_read_timeout
+    deadline: Option<Instant>,
+    /// the trailer fields of a chunked body have the limits of the headers, see
+    /// ParseOptions::max_header_line_length and ParseOptions::max_headers
+    max_trailer_line_length: usize,
+    max_trailers: usize,
+}
+
+impl<'a> BodyReader<'a> {
+    pub fn with_length(
+        reader: &'a mut BufReader<Stream>,
+        content_length: u64,
+        expect_continue: bool,
+        max_body_size: usize,
+        deadline: Option<Instant>,
+    ) -> Self {
+        Self {
+            reader,
+            framing: Framing::Length(content_length),
+            expect_continue,
+            max_body_size,
+            read: 0,
+            failed: false,
+            deadline,
+            max_trailer_line_length: 0,
+            max_trailers: 0,
+        }
+    }
+
+    pub fn chunked(
+        reader: &'a mut BufReader<Stream>,
+        expect_continue: bool,
+        max_body_size: usize,
+        deadline: Option<Instant>,
+        max_trailer_line_length: usize,
+        max_trailers: usize,
+    ) -> Self {
+        Self {
+            reader,
+            framing: Framing::Chunked {
+                remaining_in_chunk: 0,
+                done: false,
+            },
+            expect_continue,
+            max_body_size,
+            read: 0,
+            failed: false,
+            deadline,
+            max_trailer_line_length,
+            max_trailers,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        match self.framing {
+            Framing::Length(remaining) => remaining == 0,
+            Framing::Chunked { done, .. } => done,
+        }
+    }
+
+    /// True if the client is still waiting for 100 Continue before sending the body
+    pub fn awaits_continue(&self) -> bool {
+        self.expect_continue && !self.is_done()
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        read_line_before(self.reader, &mut line, MAX_CHUNK_LINE_LENGTH, self.deadline)?;
+        match line.strip_suffix("\r\n") {
+            Some(x) => Ok(x.to_string()),
+            None => Err(invalid_data(Error::InvalidChunk)),
+        }
+    }
+
+    /// Reads the next chunk size line. Returns 0 and consumes the trailer section
+    /// on the last chunk
+    fn next_chunk_size(&mut self) -> io::Result<u64> {
+        let line = self.read_line()?;
+        // NOTE: chunk extensions are ignored
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| invalid_data(Error::InvalidChunk))?;
+        if size == 0 {
+            self.read_trailers()?;
+        }
+        Ok(size)
+    }
+
+    /// Consumes the trailer section, up to the empty line. The fields are discarded but
+    /// their number and length are limited like headers, otherwise a client could keep
+    /// sending fields forever
+    fn read_trailers(&mut self) -> io::Result<()> {
+        let mut line = String::new();
+        let mut count = 0;
+        loop {
+            line.clear();
+            // NOTE: one more byte than allowed is enough to detect an oversized line
+            let limit = self.max_trailer_line_length as u64 + 1;
+            read_line_before(self.reader, &mut line, limit, self.deadline)?;
+            if line.len() > self.max_trailer_line_length {
+                let key = line.split_once(':').map(|(key, _)| key.trim().to_string());
+                return Err(invalid_data(Error::HeaderLineTooLong(key)));
+            }
+            match line.as_str() {
+                "\r\n" => return Ok(()),
+                x if !x.ends_with("\r\n") => return Err(invalid_data(Error::InvalidChunk)),
+                _ => {}
+            }
+            count += 1;
+            if count > self.max_trailers {
+                return Err(invalid_data(Error::TooManyHeaders(count)));
+            }
+        }
+    }
+}
+
+fn invalid_data(error: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Sets the read timeout of the connection to the time left before the deadline. The
+/// timeout is set again before each read, that way a client sending a few bytes at a
+/// time can not hold the connection past the deadline
+fn apply_deadline(reader: &BufReader<Stream>, deadline: Option<Instant>) -> io::Result<()> {
+    let Some(deadline) = deadline else {
+        return Ok(());
+    };
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Err(invalid_data(Error::RequestReadTimeout));
+    }
+    reader.get_ref().set_read_timeout(Some(left))
+}
+
+/// When a deadline is set, the read timeout never goes past it so a timed out read
+/// means the deadline is reached
+fn deadline_error(e: io::Error, deadline: Option<Instant>) -> io::Error {
+    let timed_out = matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    );
+    match deadline.is_some() && timed_out {
+        true => invalid_data(Error::RequestReadTimeout),
+        false => e,
+    }
+}
+
+/// Same as read_line limited to limit bytes, but each read of the connection is bounded
+/// by the deadline, see apply_deadline
+pub fn read_line_before(
+    reader: &mut BufReader<Stream>,
+    line: &mut String,
+    limit: u64,
+    deadline: Option<Instant>,
+) -> io::Result<usize> {
+    // NOTE: the bytes are appended to the allocation of line, which may come from the
+    // buffer pool
+    let mut bytes = std::mem::take(line).into_bytes();
+    let start = bytes.len();
+    loop {
+        let left = limit - (bytes.len() - start) as u64;
+        if left == 0 {
+            break;
+        }
+        apply_deadline(reader, deadline)?;
+        let available = reader.fill_buf().map_err(|e| deadline_error(e, deadline))?;
+        let available = &available[..available
+            .len()
+            .min(usize::try_from(left).unwrap_or(usize::MAX))];
+        let (n, done) = match available.iter().position(|&x| x == b'\n') {
+            Some(i) => (i + 1, true),
+            None => (available.len(), available.is_empty()),
+        };
+        bytes.extend_from_slice(&available[..n]);
+        reader.consume(n);
+        if done {
+            break;
+        }
+    }
+    let n = bytes.len() - start;
+    *line = String::from_utf8(bytes).map_err(|e| invalid_data(Error::FromUtf8Error(e)))?;
+    Ok(n)
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.failed {
+            return Err(invalid_data(Error::InvalidChunk));
+        }
+        let result = self.read_body(buf);
+        self.failed = matches!(&result, Err(e) if e.kind() != io::ErrorKind::Interrupted);
+        result
+    }
+}
+
+impl BodyReader<'_> {
+    fn read_body(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.is_done() || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.expect_continue {
+            self.expect_continue = false;
+            self.reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+
+        let to_read = match self.framing {
+            Framing::Length(remaining) => remaining,
+            Framing::Chunked {
+                remaining_in_chunk: 0,
+                ..
+            } => {
+                let size = self.next_chunk_size()?;
+                // NOTE: the announced size is enough to reject the chunk, its data is
+                // not read
+                let total = self.read.saturating_add(size);
+                if total > self.max_body_size as u64 {
+                    return Err(invalid_data(Error::PayloadTooLarge(total)));
+                }
+                self.framing = Framing::Chunked {
+                    remaining_in_chunk: size,
+                    done: size == 0,
+                };
+                if size == 0 {
+                    return Ok(0);
+                }
+                size
+            }
+            Framing::Chunked {
+                remaining_in_chunk, ..
+            } => remaining_in_chunk,
+        };
+
+        let max = buf
+            .len()
+            .min(usize::try_from(to_read).unwrap_or(usize::MAX));
+        apply_deadline(self.reader, self.deadline)?;
+        let n = self
+            .reader
+            .read(&mut buf[..max])
+            .map_err(|e| deadline_error(e, self.deadline))?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.read += n as u64;
+        if self.read > self.max_body_size as u64 {
+            return Err(invalid_data(Error::PayloadTooLarge(self.read)));
+        }
+
+        let end_of_chunk = match &mut self.framing {
+            Framing::Length(remaining) => {
+                *remaining -= n as u64;
+                false
+            }
+            Framing::Chunked {
+                remaining_in_chunk, ..
+            } => {
+                *remaining_in_chunk -= n as u64;
+                *remaining_in_chunk == 0
+            }
+        };
+        // NOTE: each chunk is followed by a CRLF
+        if end_of_chunk && !self.read_line()?.is_empty() {
+            return Err(invalid_data(Error::InvalidChunk));
+        }
+        Ok(n)
+    }
+}
+
+/// Writes each buffer as a chunk of a Transfer-Encoding: chunked body. finish must be
+/// called to send the last chunk
+pub struct ChunkedWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Sends the last chunk followed by the trailer fields, if any
+    pub fn finish(mut self, trailers: &[HttpHeader]) -> io::Result<W> {
+        self.writer.write_all(b"0\r\n")?;
+        for trailer in trailers {
+            write!(self.writer, "{}: {}\r\n", trailer.key, trailer.value)?;
+        }
+        self.writer.write_all(b"\r\n")?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // NOTE: an empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.writer, "{:x}\r\n", buf.len())?;
+        self.writer.write_all(buf)?;
+        self.writer.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Retries the writes interrupted by a signal. write_all already does, but some writers
+/// call write directly and return the error, for ex the flate2 encoders when they
+/// flush their buffer
+pub struct RetryInterrupted<W: Write>(pub W);
+
+impl<W: Write> Write for RetryInterrupted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.0.write(buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        loop {
+            match self.0.flush() {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Called with the number of bytes read each time a ProgressReader logs
+pub type ProgressHook = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Logs the number of bytes read every interval bytes, and once the end is reached
+pub struct ProgressReader<R: Read> {
+    reader: R,
+    label: String,
+    interval: u64,
+    read: u64,
+    next_log: u64,
+    done: bool,
+    hook: Option<ProgressHook>,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(reader: R, label: &str, interval: u64) -> Self {
+        Self {
+            reader,
+            label: label.to_string(),
+            // NOTE: an interval of 0 would log on every read
+            interval: interval.max(1),
+            read: 0,
+            next_log: interval.max(1),
+            done: false,
+            hook: None,
+        }
+    }
+
+    pub fn with_hook(mut self, hook: Option<ProgressHook>) -> Self {
+        self.hook = hook;
+        self
+    }
+
+    fn log(&self, message: &str) {
+        println!("{}: {}", self.label, message);
+        if let Some(hook) = &self.hook {
+            hook(self.read);
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.read += n as u64;
+        if n == 0 && !buf.is_empty() && !self.done {
+            self.done = true;
+            self.log(&format!("done, {} bytes", self.read));
+        }
+        if self.read >= self.next_log {
+            self.log(&format!("{} bytes", self.read));
+            self.next_log = (self.read / self.interval + 1) * self.interval;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+
+    /// Connection on which the peer already sent the bytes and closed its side
+    fn connection(bytes: &[u8]) -> BufReader<Stream> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(bytes).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        BufReader::new(Stream::Tcp(stream))
+    }
+
+    /// Reads the body a few bytes at a time, as a handler consuming it incrementally
+    fn read_in_small_parts(body: &mut BodyReader) -> io::Result<Vec<u8>> {
+        let mut read = Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            match body.read(&mut buf)? {
+                0 => return Ok(read),
+                n => read.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    #[test]
+    fn length_body_stops_at_its_end() {
+        let content: Vec<u8> = (0..100_000u32).map(|x| x as u8).collect();
+        let mut bytes = content.clone();
+        bytes.extend_from_slice(b"GET / HTTP/1.1\r\n");
+        let mut reader = connection(&bytes);
+
+        let mut body = BodyReader::with_length(&mut reader, 100_000, false, usize::MAX, None);
+        assert_eq!(read_in_small_parts(&mut body).unwrap(), content);
+        assert!(body.is_done());
+
+        // NOTE: the next request is left on the connection
+        let mut next = String::new();
+        reader.read_line(&mut next).unwrap();
+        assert_eq!(next, "GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    fn chunked_body_is_decoded() {
+        let mut reader =
+            connection(b"5;name=value\r\nhello\r\nb\r\n large body\r\n0\r\nTrailer: x\r\n\r\nnext");
+
+        let mut body = BodyReader::chunked(&mut reader, false, usize::MAX, None, 1024, 100);
+        assert_eq!(read_in_small_parts(&mut body).unwrap(), b"hello large body");
+        assert!(body.is_done());
+
+        let mut next = String::new();
+        reader.read_to_string(&mut next).unwrap();
+        assert_eq!(next, "next");
+    }
+
+    #[test]
+    fn invalid_chunks_are_rejected() {
+        for bytes in [&b"zz\r\nhello\r\n0\r\n\r\n"[..], b"5\r\nhelloXX0\r\n\r\n"] {
+            let mut reader = connection(bytes);
+            let mut body = BodyReader::chunked(&mut reader, false, usize::MAX, None, 1024, 100);
+            let error = read_in_small_parts(&mut body).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn chunked_body_over_the_limit_is_rejected_from_the_chunk_size() {
+        // NOTE: the data of the second chunk is never sent
+        let mut reader = connection(
+            b"3c\r\naaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n3c\r\n",
+        );
+
+        let mut body = BodyReader::chunked(&mut reader, false, 100, None, 1024, 100);
+        let error = read_in_small_parts(&mut body).unwrap_err();
+        let error = error.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(*error, Error::PayloadTooLarge(120)));
+    }
+
+    /// Fails every other call with Interrupted, as a write hit by a signal
+    struct Interrupting {
+        written: Vec<u8>,
+        interrupt: bool,
+    }
+
+    impl Write for Interrupting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.interrupt = !self.interrupt;
+            match self.interrupt {
+                true => Err(io::ErrorKind::Interrupted.into()),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn interrupted_writes_are_retried() {
+        let mut writer = RetryInterrupted(Interrupting {
+            written: Vec::new(),
+            interrupt: false,
+        });
+        assert_eq!(writer.write(b"hello").unwrap(), 5);
+        assert_eq!(writer.write(b" world").unwrap(), 6);
+        writer.flush().unwrap();
+        assert_eq!(writer.0.written, b"hello world");
+    }
+
+    #[test]
+    fn interrupted_writes_of_an_encoder_are_retried() {
+        let writer = RetryInterrupted(Interrupting {
+            written: Vec::new(),
+            interrupt: false,
+        });
+        let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+        encoder.write_all(&b"compressible ".repeat(1000)).unwrap();
+        let compressed = encoder.finish().unwrap().0.written;
+
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"compressible ".repeat(1000));
+    }
+
+    #[test]
+    fn progress_is_logged_at_each_interval_then_at_the_end() {
+        let content = vec![0; 3500];
+        let mut reader = ProgressReader::new(&content[..], "upload", 1000);
+
+        let mut read = Vec::new();
+        let mut buf = [0; 300];
+        let mut logged_at = Vec::new();
+        loop {
+            let next_log = reader.next_log;
+            let n = reader.read(&mut buf).unwrap();
+            if reader.next_log != next_log {
+                logged_at.push(reader.read);
+            }
+            if n == 0 {
+                break;
+            }
+            read.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(read, content);
+        // NOTE: logged once the read crosses each interval, not exactly on it
+        assert_eq!(logged_at, vec![1200, 2100, 3000]);
+        assert!(reader.done);
+    }
+
+    #[test]
+    fn trailers_have_the_limits_of_headers() {
+        let reject = |bytes: &[u8]| {
+            let mut reader = connection(bytes);
+            let mut body = BodyReader::chunked(&mut reader, false, usize::MAX, None, 20, 2);
+            let error = read_in_small_parts(&mut body).unwrap_err();
+            *error.into_inner().unwrap().downcast::<Error>().unwrap()
+        };
+
+        let mut reader = connection(b"2\r\nhi\r\n0\r\nA: 1\r\nB: 2\r\n\r\n");
+        let mut body = BodyReader::chunked(&mut reader, false, usize::MAX, None, 20, 2);
+        assert_eq!(read_in_small_parts(&mut body).unwrap(), b"hi");
+
+        let error = reject(b"2\r\nhi\r\n0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n");
+        assert!(matches!(error, Error::TooManyHeaders(3)));
+        // NOTE: the line is rejected without waiting for its end
+        let error = reject(b"2\r\nhi\r\n0\r\nChecksum: 0123456789abcdef");
+        assert!(matches!(error, Error::HeaderLineTooLong(Some(key)) if key == "Checksum"));
+    }
+
+    #[test]
+    fn truncated_body_is_an_unexpected_eof() {
+        let mut reader = connection(b"hello");
+
+        let mut body = BodyReader::with_length(&mut reader, 10, false, usize::MAX, None);
+        let error = read_in_small_parts(&mut body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
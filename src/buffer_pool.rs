@@ -0,0 +1,91 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// Maximum number of buffers kept for reuse, roughly one per worker thread
+const MAX_BUFFERS: usize = 64;
+/// Buffers that grew bigger than this are dropped instead of being kept, so that a single
+/// oversized request does not hold memory forever
+const MAX_CAPACITY: usize = 16 * 1024;
+
+/// Line buffers shared by all the connections, used to read the request line and the
+/// headers without allocating a new String for each line
+static POOL: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/// number of buffers taken from the pool instead of being allocated
+static HITS: AtomicUsize = AtomicUsize::new(0);
+
+/// Empty buffer, returned to the pool when dropped
+pub struct PooledBuffer(String);
+
+pub fn take() -> PooledBuffer {
+    let buffer = POOL
+        .lock()
+        .expect("Buffer pool lock should not be poisoned")
+        .pop();
+    match buffer {
+        Some(buffer) => {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            PooledBuffer(buffer)
+        }
+        None => PooledBuffer(String::new()),
+    }
+}
+
+/// Number of buffers reused since the start of the process
+pub fn hits() -> usize {
+    HITS.load(Ordering::Relaxed)
+}
+
+impl Deref for PooledBuffer {
+    type Target = String;
+
+    fn deref(&self) -> &String {
+        &self.0
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut String {
+        &mut self.0
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        // NOTE: cleared here rather than in take so that the content of a request never
+        // stays in the pool
+        let mut buffer = std::mem::take(&mut self.0);
+        buffer.clear();
+        if buffer.capacity() > MAX_CAPACITY {
+            return;
+        }
+        let mut pool = POOL
+            .lock()
+            .expect("Buffer pool lock should not be poisoned");
+        if pool.len() < MAX_BUFFERS {
+            pool.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_buffer_is_cleared_and_reused() {
+        let mut buffer = take();
+        buffer.push_str("GET /secret HTTP/1.1\r\n");
+        drop(buffer);
+
+        // NOTE: other tests share the pool, hits can only grow
+        let before = hits();
+        let buffer = take();
+        assert!(buffer.is_empty());
+        assert!(hits() > before);
+    }
+}
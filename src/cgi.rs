@@ -0,0 +1,162 @@
+use std::{
+    io::{self, Read},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+use crate::config::Config;
+use crate::http::{HttpRequest, HttpResponse, HttpStatus};
+use crate::Result;
+
+/// Runs the script as a cgi program: the request headers are passed as environment
+/// variables, the body is streamed to its stdin and its stdout is the response.
+/// Only executables inside the cgi directory can be run. A chunked body has no
+/// CONTENT_LENGTH, the script must read stdin until the end
+/// https://datatracker.ietf.org/doc/html/rfc3875
+pub fn run(request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    let Some(cgi_dir) = &config.cgi_dir else {
+        return Ok(HttpResponse::not_found());
+    };
+    let script = &request.params["script"];
+    let cgi_dir = cgi_dir.canonicalize()?;
+    // NOTE: resolves .. and symlinks so the script can not escape the directory
+    let Ok(path) = cgi_dir.join(script).canonicalize() else {
+        return Ok(HttpResponse::not_found());
+    };
+    if !path.starts_with(&cgi_dir) || !path.is_file() {
+        return Ok(HttpResponse::not_found());
+    }
+
+    let mut command = Command::new(&path);
+    command
+        .current_dir(&cgi_dir)
+        .env("GATEWAY_INTERFACE", "CGI/1.1")
+        .env("SERVER_PROTOCOL", request.version.as_ref())
+        .env("SERVER_SOFTWARE", env!("CARGO_PKG_NAME"))
+        .env("REQUEST_METHOD", request.method.as_ref())
+        .env("SCRIPT_NAME", &request.path)
+        .env("QUERY_STRING", query_string(request));
+    for header in request.headers.iter() {
+        let name = header.key.to_uppercase().replace('-', "_");
+        match name.as_ref() {
+            // NOTE: HTTP_PROXY would be taken as the proxy of the outgoing requests of
+            // the script, see httpoxy (CVE-2016-5385)
+            "PROXY" => continue,
+            "CONTENT_LENGTH" | "CONTENT_TYPE" => command.env(name, &header.value),
+            _ => command.env(format!("HTTP_{}", name), &header.value),
+        };
+    }
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("Stdin should be piped");
+    let mut stdout = child.stdout.take().expect("Stdout should be piped");
+
+    // NOTE: stdin is written from another thread, otherwise a script writing a lot
+    // before reading its input would block both processes
+    let body = request.body_reader();
+    let (written, output) = thread::scope(|scope| {
+        let writer = scope.spawn(move || {
+            let written = io::copy(body, &mut stdin);
+            drop(stdin);
+            written
+        });
+        let mut output = Vec::new();
+        let read = stdout.read_to_end(&mut output).map(|_| output);
+        (
+            writer.join().expect("Cgi stdin thread should not panic"),
+            read,
+        )
+    });
+    let output = output?;
+    let status = child.wait()?;
+    // NOTE: the script may exit without reading its input
+    if let Err(e) = written {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            Err(e)?;
+        }
+    }
+
+    if !status.success() {
+        println!("cgi script {:?} failed: {}", path, status);
+        return Ok(HttpResponse::empty_response(HttpStatus::BadGateway502));
+    }
+    Ok(parse_output(output, &path))
+}
+
+/// The query is rebuilt from its parsed parameters as the raw one is not kept
+fn query_string(request: &HttpRequest) -> String {
+    let mut params: Vec<_> = request.query.iter().collect();
+    params.sort();
+    serde_urlencoded::to_string(params).unwrap_or_default()
+}
+
+/// The output starts with headers, at least Content-Type or Location, separated from
+/// the body by an empty line. Status sets the status code, it defaults to 200 or to
+/// 302 with a Location
+fn parse_output(output: Vec<u8>, path: &Path) -> HttpResponse {
+    let bad_gateway = |reason: &str| {
+        println!(
+            "cgi script {:?} returned an invalid response: {}",
+            path, reason
+        );
+        HttpResponse::empty_response(HttpStatus::BadGateway502)
+    };
+
+    let separator = [&b"\r\n\r\n"[..], b"\n\n"]
+        .iter()
+        .filter_map(|x| {
+            output
+                .windows(x.len())
+                .position(|w| w == *x)
+                .map(|i| (i, x.len()))
+        })
+        .min();
+    let Some((end, separator_length)) = separator else {
+        return bad_gateway("missing end of headers");
+    };
+    let Ok(head) = std::str::from_utf8(&output[..end]) else {
+        return bad_gateway("headers are not valid utf8");
+    };
+
+    let mut headers = Vec::new();
+    for line in head.lines() {
+        match line.split_once(':') {
+            Some((key, value)) => headers.push((key.trim(), value.trim())),
+            None => return bad_gateway("invalid header line"),
+        }
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    };
+
+    let status = match (header("status"), header("location")) {
+        (Some(status), _) => match status.get(..3).and_then(|x| x.parse().ok()) {
+            Some(code) => HttpStatus::from_code(code),
+            None => return bad_gateway("invalid status"),
+        },
+        (None, Some(_)) => Some(HttpStatus::Found302),
+        (None, None) => Some(HttpStatus::Ok200),
+    };
+    let Some(status) = status else {
+        return bad_gateway("unsupported status");
+    };
+
+    let body = output[end + separator_length..].to_vec();
+    let content_type = header("content-type").unwrap_or("application/octet-stream");
+    let mut response = HttpResponse::bytes_response(body, content_type);
+    response.status = status;
+    for (key, value) in headers.iter() {
+        if !key.eq_ignore_ascii_case("status") && !key.eq_ignore_ascii_case("content-length") {
+            response.set_header(key, value);
+        }
+    }
+    response
+}
@@ -0,0 +1,820 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::Deserialize;
+use strum_macros::EnumString;
+
+use crate::acl::Acl;
+use crate::body::ProgressHook;
+use crate::date::format_http_date;
+use crate::directory::Directory;
+use crate::drain::Drain;
+use crate::http::{is_token, ContentCoding, HttpHeader, ParseOptions};
+use crate::mime;
+use crate::router::Router;
+use crate::sse::EventStreams;
+use crate::uploads::Uploads;
+use crate::{Error, Result};
+
+#[derive(EnumString, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConcurrencyMode {
+    /// connections are handled by a fixed size thread pool
+    #[default]
+    #[strum(serialize = "pool")]
+    Pool,
+    /// a detached thread is spawned for each connection
+    #[strum(serialize = "thread-per-conn")]
+    ThreadPerConnection,
+}
+
+/// Form of the Retry-After header, both are allowed by the spec but some clients only
+/// understand one
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-10.2.3
+#[derive(EnumString, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetryAfterFormat {
+    /// delay in seconds, for ex 120
+    #[default]
+    #[strum(serialize = "seconds")]
+    Seconds,
+    /// date after which to retry, for ex Sun, 06 Nov 1994 08:49:37 GMT
+    #[strum(serialize = "date")]
+    Date,
+}
+
+impl RetryAfterFormat {
+    /// Retry-After value for a retry in seconds from now
+    pub fn format(&self, seconds: u64, now: SystemTime) -> String {
+        match self {
+            Self::Seconds => seconds.to_string(),
+            Self::Date => format_http_date(now + Duration::from_secs(seconds)),
+        }
+    }
+}
+
+/// Smallest accepted read/write buffer size, below that the syscall overhead dominates
+pub const MIN_BUFFER_SIZE: usize = 1024;
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+#[derive(Clone)]
+pub struct Config {
+    pub directory: Directory,
+    /// the same routes are served on each port
+    pub ports: Vec<u16>,
+    /// when set, the server listens on this unix domain socket instead of tcp
+    pub unix_socket: Option<PathBuf>,
+    /// number of workers of the thread pool
+    pub threads: usize,
+    /// an idle persistent connection is closed after this time
+    pub keep_alive_timeout: Duration,
+    /// a connection waiting for a worker or for a request is closed after this time,
+    /// see server::reap_idle
+    pub idle_timeout: Option<Duration>,
+    /// requests served on a connection before it is closed
+    pub max_requests_per_connection: usize,
+    /// connections handled or queued at the same time, the following ones get a 503
+    pub max_connections: Option<usize>,
+    /// Retry-After of the 503 sent when max_connections is reached and of the 429, in
+    /// seconds
+    pub retry_after: u64,
+    pub retry_after_format: RetryAfterFormat,
+    /// gzip level, from 0 (no compression) to 9 (best compression)
+    pub compression_level: u32,
+    /// codings used to compress responses, the first ones are preferred when the client
+    /// accepts several equally
+    pub encoding_priority: Vec<ContentCoding>,
+    /// content types that are compressed, type/* matches all the subtypes
+    pub compressible_types: Vec<String>,
+    /// directory of the executables run by /cgi/<script>, disabled when None
+    pub cgi_dir: Option<PathBuf>,
+    /// prints the registered routes when the server is built
+    pub list_routes: bool,
+    /// GET / returns a page with the version, the routes and the uptime, unless
+    /// root_file is set
+    pub landing_page: bool,
+    /// scheme and authority used in absolute urls, for ex https://example.com. When None,
+    /// it is derived from the request, see HttpRequest::base_url
+    pub base_url: Option<String>,
+    /// trust the X-Forwarded-Host and X-Forwarded-Proto headers set by a reverse proxy
+    pub behind_proxy: bool,
+    /// set when the server is built, for the uptime of the landing page
+    pub started: Instant,
+    /// shared with the handlers to start draining, see Drain
+    pub drain: Drain,
+    /// shared with the handlers to limit concurrent uploads, see Uploads
+    pub uploads: Uploads,
+    /// uploads a single client ip can have in progress, the following ones get a 429
+    pub max_uploads_per_client: Option<usize>,
+    /// shared with the handlers to limit concurrent event streams, see EventStreams
+    pub event_streams: EventStreams,
+    /// event streams open at the same time, the following ones get a 503. With a thread
+    /// pool, always kept below threads so that streams leave a worker for the other
+    /// requests. Unlimited when None in thread-per-conn mode
+    pub max_event_streams: Option<usize>,
+    /// time between the start of draining and the shutdown of the server
+    pub drain_grace_period: Duration,
+    /// enables POST /_shutdown for the requests sending it in X-Admin-Token, and is also
+    /// required by the local-only POST /_drain and /_reload
+    pub admin_token: Option<String>,
+    /// longest wait before accepting again after an accept error, see Backoff
+    pub accept_backoff_max: Duration,
+    /// a request taking longer gets a 503 instead of the handler's response, see
+    /// handle_connection
+    pub request_timeout: Option<Duration>,
+    /// adds a Server-Timing header with the time spent on the request
+    pub server_timing: bool,
+    /// enables inject_latency and inject_failure_rate, so that they are never applied
+    /// by accident
+    pub fault_injection: bool,
+    /// sleep before each response, for testing clients
+    pub inject_latency: Option<Duration>,
+    /// fraction of the responses replaced by a 500, between 0 and 1
+    pub inject_failure_rate: f64,
+    /// serve files and directories whose name starts with a dot, such as .env or .git
+    pub serve_hidden: bool,
+    /// max-age in seconds of the Cache-Control sent with files, 0 sends no-store
+    pub cache_max_age: Option<u64>,
+    /// serve files through symlinks, which can point outside of the directory
+    pub follow_symlinks: bool,
+    /// logs the progress of uploads
+    pub verbose: bool,
+    /// with verbose, the progress of an upload is logged each time this many bytes are
+    /// received
+    pub progress_interval: u64,
+    /// with verbose, called with the bytes received each time the progress of an upload
+    /// is logged. Only set from code, for ex to monitor uploads
+    pub on_progress: Option<ProgressHook>,
+    /// permissions of the files uploaded to /files, only applied on unix
+    pub upload_mode: Option<u32>,
+    /// uploads are synced to disk before being renamed into place, so that a crash can
+    /// not leave an empty or truncated file under the final name
+    pub sync_uploads: bool,
+    /// guess the content type of files with an unknown extension from their first bytes
+    pub sniff_content_type: bool,
+    /// adds X-Content-Type-Options: nosniff and X-Frame-Options: DENY to all responses
+    pub security_headers: bool,
+    /// Content-Security-Policy sent with all responses
+    pub content_security_policy: Option<String>,
+    /// headers added to all responses, see --add-header
+    pub extra_headers: Vec<HttpHeader>,
+    /// extra headers replace the headers of the response with the same name instead of
+    /// being skipped
+    pub override_headers: bool,
+    /// header names are sent in their canonical case, see canonicalize_header_name
+    pub canonical_header_names: bool,
+    /// served on GET /, an empty 200 is returned when None
+    pub root_file: Option<PathBuf>,
+    pub acl: Acl,
+    pub parse_options: ParseOptions,
+    /// listen backlog, None keeps the OS default
+    pub backlog: Option<i32>,
+    pub mode: ConcurrencyMode,
+    /// allows CONNECT tunnels to the authorities of the CONNECT allow rules, see Acl
+    pub enable_connect: bool,
+    /// a tunnel where nothing is sent in either direction is closed after this time
+    pub tunnel_idle_timeout: Duration,
+    /// POST requests can be routed as PUT or DELETE with X-HTTP-Method-Override or
+    /// ?_method=, for clients that can only send GET and POST
+    pub allow_method_override: bool,
+    /// capacity of the BufReader wrapping each connection
+    pub read_buffer_size: usize,
+    /// capacity of the BufWriter wrapping each connection
+    pub write_buffer_size: usize,
+    /// maximum size of the serialized response headers, a response above it is replaced
+    /// by a 500
+    pub max_response_headers_size: usize,
+    pub router: Router,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            directory: Directory::default(),
+            ports: vec![4221],
+            unix_socket: None,
+            threads: 4,
+            keep_alive_timeout: Duration::from_secs(5),
+            idle_timeout: None,
+            max_requests_per_connection: 100,
+            max_connections: None,
+            retry_after: 1,
+            retry_after_format: RetryAfterFormat::default(),
+            compression_level: 6,
+            encoding_priority: vec![ContentCoding::Gzip, ContentCoding::Deflate],
+            compressible_types: mime::COMPRESSIBLE_TYPES.map(String::from).to_vec(),
+            cgi_dir: None,
+            list_routes: false,
+            landing_page: false,
+            base_url: None,
+            behind_proxy: false,
+            started: Instant::now(),
+            drain: Drain::default(),
+            uploads: Uploads::default(),
+            max_uploads_per_client: None,
+            event_streams: EventStreams::default(),
+            max_event_streams: None,
+            drain_grace_period: Duration::from_secs(10),
+            admin_token: None,
+            accept_backoff_max: Duration::from_secs(1),
+            request_timeout: None,
+            server_timing: false,
+            fault_injection: false,
+            inject_latency: None,
+            inject_failure_rate: 0.0,
+            serve_hidden: false,
+            cache_max_age: None,
+            follow_symlinks: false,
+            verbose: false,
+            progress_interval: 1024 * 1024,
+            on_progress: None,
+            upload_mode: None,
+            sync_uploads: false,
+            sniff_content_type: false,
+            security_headers: false,
+            content_security_policy: None,
+            extra_headers: Vec::new(),
+            override_headers: false,
+            canonical_header_names: false,
+            root_file: None,
+            acl: Acl::default(),
+            parse_options: ParseOptions::default(),
+            backlog: None,
+            mode: ConcurrencyMode::default(),
+            enable_connect: false,
+            tunnel_idle_timeout: Duration::from_secs(60),
+            allow_method_override: false,
+            read_buffer_size: DEFAULT_BUFFER_SIZE,
+            write_buffer_size: DEFAULT_BUFFER_SIZE,
+            max_response_headers_size: 64 * 1024,
+            router: Router::default(),
+        }
+    }
+}
+
+/// port = 4221 or port = [80, 8080]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Ports {
+    One(u16),
+    Many(Vec<u16>),
+}
+
+/// Content of the toml config file. Keys are the cli flags with underscores instead
+/// of dashes, for ex max_body_size = 1024
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    directory: Option<String>,
+    port: Option<Ports>,
+    unix_socket: Option<PathBuf>,
+    threads: Option<usize>,
+    keep_alive_timeout: Option<String>,
+    idle_timeout: Option<String>,
+    max_requests_per_connection: Option<usize>,
+    max_connections: Option<usize>,
+    retry_after: Option<u64>,
+    retry_after_format: Option<String>,
+    compression_level: Option<u32>,
+    encoding_priority: Option<Vec<String>>,
+    compressible_types: Option<Vec<String>>,
+    cgi_dir: Option<PathBuf>,
+    list_routes: Option<bool>,
+    landing_page: Option<bool>,
+    base_url: Option<String>,
+    behind_proxy: Option<bool>,
+    max_uploads_per_client: Option<usize>,
+    max_event_streams: Option<usize>,
+    drain_grace_period: Option<String>,
+    admin_token: Option<String>,
+    accept_backoff_max: Option<String>,
+    request_timeout: Option<String>,
+    server_timing: Option<bool>,
+    fault_injection: Option<bool>,
+    inject_latency: Option<String>,
+    inject_failure_rate: Option<f64>,
+    serve_hidden: Option<bool>,
+    cache_max_age: Option<u64>,
+    follow_symlinks: Option<bool>,
+    verbose: Option<bool>,
+    progress_interval: Option<u64>,
+    upload_mode: Option<String>,
+    sync_uploads: Option<bool>,
+    sniff_content_type: Option<bool>,
+    security_headers: Option<bool>,
+    content_security_policy: Option<String>,
+    #[serde(default)]
+    add_header: Vec<String>,
+    override_headers: Option<bool>,
+    canonical_header_names: Option<bool>,
+    root_file: Option<PathBuf>,
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+    backlog: Option<i32>,
+    mode: Option<String>,
+    enable_connect: Option<bool>,
+    tunnel_idle_timeout: Option<String>,
+    allow_method_override: Option<bool>,
+    read_buffer_size: Option<usize>,
+    write_buffer_size: Option<usize>,
+    max_response_headers_size: Option<usize>,
+    merge_slashes: Option<bool>,
+    strip_trailing_slash: Option<bool>,
+    max_body_size: Option<usize>,
+    max_request_line_length: Option<usize>,
+    max_header_line_length: Option<usize>,
+    max_headers: Option<usize>,
+    max_path_depth: Option<usize>,
+    max_query_length: Option<usize>,
+    max_query_params: Option<usize>,
+    request_read_timeout: Option<String>,
+}
+
+impl Config {
+    /// Reads a toml config file, missing values are set to their default
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let file: FileConfig = toml::from_str(&std::fs::read_to_string(path)?)?;
+        let mut config = Self::default();
+
+        if let Some(x) = file.directory {
+            config.directory = Directory::new(x);
+        }
+        match file.port {
+            Some(Ports::One(x)) => config.ports = vec![x],
+            Some(Ports::Many(x)) => config.ports = x,
+            None => {}
+        }
+        config.unix_socket = file.unix_socket;
+        if let Some(x) = file.threads {
+            config.threads = x;
+        }
+        if let Some(x) = file.keep_alive_timeout {
+            config.keep_alive_timeout = duration(&x)?;
+        }
+        if let Some(x) = file.idle_timeout {
+            config.idle_timeout = Some(duration(&x)?);
+        }
+        if let Some(x) = file.max_requests_per_connection {
+            config.max_requests_per_connection = x;
+        }
+        config.max_connections = file.max_connections;
+        if let Some(x) = file.retry_after {
+            config.retry_after = x;
+        }
+        if let Some(x) = file.retry_after_format {
+            config.retry_after_format = x.parse()?;
+        }
+        if let Some(x) = file.compression_level {
+            config.compression_level = compression_level(x)?;
+        }
+        if let Some(x) = file.encoding_priority {
+            config.encoding_priority = encoding_priority(x.iter().map(|x| x.as_str()))?;
+        }
+        if let Some(x) = file.compressible_types {
+            config.compressible_types = x.iter().map(|x| x.trim().to_string()).collect();
+        }
+        config.cgi_dir = file.cgi_dir;
+        if let Some(x) = file.list_routes {
+            config.list_routes = x;
+        }
+        if let Some(x) = file.landing_page {
+            config.landing_page = x;
+        }
+        if let Some(x) = file.base_url {
+            config.base_url = Some(base_url(&x)?);
+        }
+        if let Some(x) = file.behind_proxy {
+            config.behind_proxy = x;
+        }
+        config.max_uploads_per_client = file.max_uploads_per_client;
+        config.max_event_streams = file.max_event_streams;
+        if let Some(x) = file.drain_grace_period {
+            config.drain_grace_period = duration(&x)?;
+        }
+        config.admin_token = file.admin_token;
+        if let Some(x) = file.accept_backoff_max {
+            config.accept_backoff_max = duration(&x)?;
+        }
+        if let Some(x) = file.request_timeout {
+            config.request_timeout = Some(duration(&x)?);
+        }
+        if let Some(x) = file.server_timing {
+            config.server_timing = x;
+        }
+        if let Some(x) = file.fault_injection {
+            config.fault_injection = x;
+        }
+        if let Some(x) = file.inject_latency {
+            config.inject_latency = Some(duration(&x)?);
+        }
+        if let Some(x) = file.inject_failure_rate {
+            config.inject_failure_rate = failure_rate(x)?;
+        }
+        if let Some(x) = file.serve_hidden {
+            config.serve_hidden = x;
+        }
+        config.cache_max_age = file.cache_max_age;
+        if let Some(x) = file.follow_symlinks {
+            config.follow_symlinks = x;
+        }
+        if let Some(x) = file.verbose {
+            config.verbose = x;
+        }
+        if let Some(x) = file.progress_interval {
+            config.progress_interval = x;
+        }
+        if let Some(x) = file.upload_mode {
+            config.upload_mode = Some(file_mode(&x)?);
+        }
+        if let Some(x) = file.sync_uploads {
+            config.sync_uploads = x;
+        }
+        if let Some(x) = file.sniff_content_type {
+            config.sniff_content_type = x;
+        }
+        if let Some(x) = file.security_headers {
+            config.security_headers = x;
+        }
+        config.content_security_policy = file.content_security_policy;
+        for header in file.add_header {
+            config.extra_headers.push(extra_header(&header)?);
+        }
+        if let Some(x) = file.override_headers {
+            config.override_headers = x;
+        }
+        if let Some(x) = file.canonical_header_names {
+            config.canonical_header_names = x;
+        }
+        config.root_file = file.root_file;
+        for rule in file.allow {
+            config.acl.allow.push(Acl::parse_rule(&rule)?);
+        }
+        for rule in file.deny {
+            config.acl.deny.push(Acl::parse_rule(&rule)?);
+        }
+        config.backlog = file.backlog;
+        if let Some(x) = file.mode {
+            config.mode = x.parse()?;
+        }
+        if let Some(x) = file.enable_connect {
+            config.enable_connect = x;
+        }
+        if let Some(x) = file.tunnel_idle_timeout {
+            config.tunnel_idle_timeout = duration(&x)?;
+        }
+        if let Some(x) = file.allow_method_override {
+            config.allow_method_override = x;
+        }
+        if let Some(x) = file.read_buffer_size {
+            config.read_buffer_size = buffer_size(x)?;
+        }
+        if let Some(x) = file.write_buffer_size {
+            config.write_buffer_size = buffer_size(x)?;
+        }
+        if let Some(x) = file.max_response_headers_size {
+            config.max_response_headers_size = x;
+        }
+        if let Some(x) = file.merge_slashes {
+            config.parse_options.merge_slashes = x;
+        }
+        if let Some(x) = file.strip_trailing_slash {
+            config.parse_options.strip_trailing_slash = x;
+        }
+        if let Some(x) = file.max_body_size {
+            config.parse_options.max_body_size = x;
+        }
+        if let Some(x) = file.max_request_line_length {
+            config.parse_options.max_request_line_length = x;
+        }
+        if let Some(x) = file.max_header_line_length {
+            config.parse_options.max_header_line_length = x;
+        }
+        if let Some(x) = file.max_headers {
+            config.parse_options.max_headers = x;
+        }
+        if let Some(x) = file.max_path_depth {
+            config.parse_options.max_path_depth = x;
+        }
+        if let Some(x) = file.max_query_length {
+            config.parse_options.max_query_length = x;
+        }
+        if let Some(x) = file.max_query_params {
+            config.parse_options.max_query_params = x;
+        }
+        if let Some(x) = file.request_read_timeout {
+            config.parse_options.request_read_timeout = Some(duration(&x)?);
+        }
+        Ok(config)
+    }
+
+    /// args should not contain the program name.
+    /// Precedence is cli flags > config file given with --config > defaults.
+    /// Acl rules from the file and the cli are combined
+    pub fn from_args<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut config = match args.iter().position(|x| x == "--config") {
+            Some(i) => {
+                let path = args
+                    .get(i + 1)
+                    .ok_or_else(|| Error::MissingArgValue(args[i].clone()))?;
+                Self::from_file(Path::new(path))?
+            }
+            None => Self::default(),
+        };
+        let mut args = args.into_iter();
+        // NOTE: --port is repeatable, the ports given replace the default ones
+        let mut ports = Vec::new();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| Error::MissingArgValue(arg.clone()))
+            };
+            match arg.as_ref() {
+                // NOTE: already loaded
+                "--config" => {
+                    value()?;
+                }
+                "--directory" => config.directory = Directory::new(value()?),
+                "--port" => ports.push(value()?.parse()?),
+                "--unix-socket" => config.unix_socket = Some(PathBuf::from(value()?)),
+                "--threads" => config.threads = value()?.parse()?,
+                "--keep-alive-timeout" => config.keep_alive_timeout = duration(&value()?)?,
+                "--idle-timeout" => config.idle_timeout = Some(duration(&value()?)?),
+                "--max-requests-per-connection" => {
+                    config.max_requests_per_connection = value()?.parse()?
+                }
+                "--max-connections" => config.max_connections = Some(value()?.parse()?),
+                "--retry-after" => config.retry_after = value()?.parse()?,
+                "--retry-after-format" => config.retry_after_format = value()?.parse()?,
+                "--compression-level" => {
+                    config.compression_level = compression_level(value()?.parse()?)?
+                }
+                "--encoding-priority" => {
+                    config.encoding_priority = encoding_priority(value()?.split(','))?
+                }
+                "--compressible-types" => {
+                    config.compressible_types =
+                        value()?.split(',').map(|x| x.trim().to_string()).collect()
+                }
+                "--cgi-dir" => config.cgi_dir = Some(PathBuf::from(value()?)),
+                "--list-routes" => config.list_routes = true,
+                "--landing-page" => config.landing_page = true,
+                "--base-url" => config.base_url = Some(base_url(&value()?)?),
+                "--behind-proxy" => config.behind_proxy = true,
+                "--max-uploads-per-client" => {
+                    config.max_uploads_per_client = Some(value()?.parse()?)
+                }
+                "--max-event-streams" => config.max_event_streams = Some(value()?.parse()?),
+                "--drain-grace-period" => config.drain_grace_period = duration(&value()?)?,
+                "--admin-token" => config.admin_token = Some(value()?),
+                "--accept-backoff-max" => config.accept_backoff_max = duration(&value()?)?,
+                "--request-timeout" => config.request_timeout = Some(duration(&value()?)?),
+                "--server-timing" => config.server_timing = true,
+                "--fault-injection" => config.fault_injection = true,
+                "--inject-latency" => config.inject_latency = Some(duration(&value()?)?),
+                "--inject-failure-rate" => {
+                    config.inject_failure_rate = failure_rate(value()?.parse()?)?
+                }
+                "--serve-hidden" => config.serve_hidden = true,
+                "--cache-max-age" => config.cache_max_age = Some(value()?.parse()?),
+                "--follow-symlinks" => config.follow_symlinks = true,
+                "--verbose" => config.verbose = true,
+                "--progress-interval" => config.progress_interval = value()?.parse()?,
+                "--upload-mode" => config.upload_mode = Some(file_mode(&value()?)?),
+                "--sync-uploads" => config.sync_uploads = true,
+                "--sniff-content-type" => config.sniff_content_type = true,
+                "--security-headers" => config.security_headers = true,
+                "--content-security-policy" => config.content_security_policy = Some(value()?),
+                "--root-file" => config.root_file = Some(PathBuf::from(value()?)),
+                "--add-header" => config.extra_headers.push(extra_header(&value()?)?),
+                "--override-headers" => config.override_headers = true,
+                "--canonical-header-names" => config.canonical_header_names = true,
+                "--allow" => config.acl.allow.push(Acl::parse_rule(&value()?)?),
+                "--deny" => config.acl.deny.push(Acl::parse_rule(&value()?)?),
+                "--backlog" => config.backlog = Some(value()?.parse()?),
+                "--mode" => config.mode = value()?.parse()?,
+                "--enable-connect" => config.enable_connect = true,
+                "--tunnel-idle-timeout" => config.tunnel_idle_timeout = duration(&value()?)?,
+                "--allow-method-override" => config.allow_method_override = true,
+                "--read-buffer-size" => config.read_buffer_size = buffer_size(value()?.parse()?)?,
+                "--write-buffer-size" => config.write_buffer_size = buffer_size(value()?.parse()?)?,
+                "--max-response-headers-size" => {
+                    config.max_response_headers_size = value()?.parse()?
+                }
+                "--merge-slashes" => config.parse_options.merge_slashes = true,
+                "--strip-trailing-slash" => config.parse_options.strip_trailing_slash = true,
+                "--max-body-size" => config.parse_options.max_body_size = value()?.parse()?,
+                "--max-request-line-length" => {
+                    config.parse_options.max_request_line_length = value()?.parse()?
+                }
+                "--max-header-line-length" => {
+                    config.parse_options.max_header_line_length = value()?.parse()?
+                }
+                "--max-headers" => config.parse_options.max_headers = value()?.parse()?,
+                "--max-path-depth" => config.parse_options.max_path_depth = value()?.parse()?,
+                "--max-query-length" => config.parse_options.max_query_length = value()?.parse()?,
+                "--max-query-params" => config.parse_options.max_query_params = value()?.parse()?,
+                "--request-read-timeout" => {
+                    config.parse_options.request_read_timeout = Some(duration(&value()?)?)
+                }
+                _ => Err(Error::UnknownArg(arg.clone()))?,
+            }
+        }
+        if !ports.is_empty() {
+            config.ports = ports;
+        }
+        Ok(config)
+    }
+}
+
+fn buffer_size(size: usize) -> Result<usize> {
+    if size < MIN_BUFFER_SIZE {
+        Err(Error::InvalidBufferSize(size))?;
+    }
+    Ok(size)
+}
+
+fn compression_level(level: u32) -> Result<u32> {
+    if level > 9 {
+        Err(Error::InvalidCompressionLevel(level))?;
+    }
+    Ok(level)
+}
+
+/// Parses a header given as "Key: value". The key must be a token and the value must
+/// not contain control characters, which could split the response
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-5.1
+fn extra_header(s: &str) -> Result<HttpHeader> {
+    let invalid = || Error::InvalidHeaderArg(s.to_string());
+    let (key, value) = s.split_once(':').ok_or_else(invalid)?;
+    let (key, value) = (key.trim(), value.trim());
+    if !is_token(key) || value.chars().any(|c| c.is_control() && c != '\t') {
+        Err(invalid())?;
+    }
+    Ok(HttpHeader {
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
+
+/// Only the codings the server implements are accepted
+fn encoding_priority<'a>(codings: impl Iterator<Item = &'a str>) -> Result<Vec<ContentCoding>> {
+    codings
+        .map(|x| {
+            ContentCoding::from_str(x.trim())
+                .map_err(|_| Error::UnsupportedContentCoding(x.to_string()))
+        })
+        .collect()
+}
+
+/// http or https url without path, the trailing slash is removed
+fn base_url(url: &str) -> Result<String> {
+    let authority = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))
+        .map(|x| x.trim_end_matches('/'));
+    match authority {
+        Some(x) if !x.is_empty() && !x.contains('/') => Ok(url.trim_end_matches('/').to_string()),
+        _ => Err(Error::InvalidBaseUrl(url.to_string()))?,
+    }
+}
+
+fn failure_rate(rate: f64) -> Result<f64> {
+    if !(0.0..=1.0).contains(&rate) {
+        Err(Error::InvalidFailureRate(rate))?;
+    }
+    Ok(rate)
+}
+
+/// Parses permissions written in octal such as 0644
+fn file_mode(s: &str) -> Result<u32> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(Error::InvalidFileMode(s.to_string())),
+    }
+}
+
+/// Parses a duration such as 500ms, 30s or 2m. A number without unit is in seconds
+fn duration(s: &str) -> Result<Duration> {
+    let invalid = || Error::InvalidDuration(s.to_string());
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        "m" => Ok(Duration::from_secs(
+            value.checked_mul(60).ok_or_else(invalid)?,
+        )),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn cli_flags_override_the_file_which_overrides_the_defaults() {
+        let path =
+            std::env::temp_dir().join(format!("http-server-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "directory = \"/srv/files\"\nport = 8080\nthreads = 8\ncompression_level = 9\nmax_body_size = 1024\n",
+        )
+        .unwrap();
+
+        let config = Config::from_args(args(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--threads",
+            "2",
+        ]));
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        // cli
+        assert_eq!(config.threads, 2);
+        // file
+        assert_eq!(config.directory.path(), PathBuf::from("/srv/files"));
+        assert_eq!(config.ports, vec![8080]);
+        assert_eq!(config.compression_level, 9);
+        assert_eq!(config.parse_options.max_body_size, 1024);
+        // default
+        assert_eq!(config.keep_alive_timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn repeated_port_flags_replace_the_default_port() {
+        assert_eq!(Config::from_args(args(&[])).unwrap().ports, vec![4221]);
+        let config = Config::from_args(args(&["--port", "80", "--port", "8080"])).unwrap();
+        assert_eq!(config.ports, vec![80, 8080]);
+    }
+
+    #[test]
+    fn retry_after_is_formatted_as_seconds_or_date() {
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(RetryAfterFormat::Seconds.format(120, now), "120");
+        assert_eq!(
+            RetryAfterFormat::Date.format(120, now),
+            "Sun, 06 Nov 1994 08:51:37 GMT"
+        );
+        let config = Config::from_args(args(&["--retry-after-format", "date"])).unwrap();
+        assert_eq!(config.retry_after_format, RetryAfterFormat::Date);
+        assert!(Config::from_args(args(&["--retry-after-format", "minutes"])).is_err());
+    }
+
+    #[test]
+    fn encoding_priority_only_lists_supported_codings() {
+        assert_eq!(
+            encoding_priority("deflate, gzip".split(',')).unwrap(),
+            vec![ContentCoding::Deflate, ContentCoding::Gzip]
+        );
+        assert!(encoding_priority("br,gzip".split(',')).is_err());
+    }
+
+    #[test]
+    fn failure_rate_is_a_fraction() {
+        assert_eq!(failure_rate(0.0).unwrap(), 0.0);
+        assert_eq!(failure_rate(1.0).unwrap(), 1.0);
+        assert!(failure_rate(1.5).is_err());
+        assert!(failure_rate(-0.1).is_err());
+    }
+
+    #[test]
+    fn file_modes_are_octal() {
+        assert_eq!(file_mode("0644").unwrap(), 0o644);
+        assert_eq!(file_mode("600").unwrap(), 0o600);
+        for invalid in ["0999", "rw-r--r--", "17777", ""] {
+            assert!(file_mode(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn durations_accept_a_unit() {
+        assert_eq!(duration("10").unwrap(), Duration::from_secs(10));
+        assert_eq!(duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(duration("2m").unwrap(), Duration::from_secs(120));
+        assert!(duration("2h").is_err());
+        assert!(duration("ms").is_err());
+        assert!(matches!(
+            duration("999999999999999999m"),
+            Err(Error::InvalidDuration(_))
+        ));
+    }
+}
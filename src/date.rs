@@ -0,0 +1,180 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// Years accepted by parse_http_date. The four digits of IMF-fixdate, it also keeps
+/// the arithmetic of days_from_civil far from overflowing
+const YEARS: std::ops::RangeInclusive<i64> = 1..=9999;
+
+/// Parses an HTTP-date. All three formats must be accepted by recipients
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-5.6.7
+/// - IMF-fixdate: Sun, 06 Nov 1994 08:49:37 GMT
+/// - rfc850: Sunday, 06-Nov-94 08:49:37 GMT
+/// - asctime: Sun Nov  6 08:49:37 1994
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let parts: Vec<_> = s.split_whitespace().collect();
+    let (day, month, year, time) = match parts.as_slice() {
+        [_, day, month, year, time, "GMT"] => (*day, *month, year.parse().ok()?, *time),
+        [_, date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+            // NOTE: two digit years are interpreted in the 1970-2069 range
+            let year: i64 = year.parse().ok()?;
+            // NOTE: a longer year is taken as is, the range is checked below
+            let year = match year {
+                0..=69 => 2000 + year,
+                70..=99 => 1900 + year,
+                year => year,
+            };
+            (day, month, year, *time)
+        }
+        [_, month, day, time, year] => (*day, *month, year.parse().ok()?, *time),
+        _ => return None,
+    };
+
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|x| *x == month)? as i64 + 1;
+    let mut time = time.split(':').map(|x| x.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !YEARS.contains(&year)
+        || !(1..=31).contains(&day)
+        || !(0..=23).contains(&hour)
+        || !(0..=59).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Formats a time as an IMF-fixdate, the format senders must use, for ex
+/// Sun, 06 Nov 1994 08:49:37 GMT. Times before the epoch are formatted as the epoch
+pub fn format_http_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0) as i64;
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days);
+    // NOTE: 1970-01-01 was a thursday
+    let weekday = WEEKDAYS[((days + 4) % 7) as usize];
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        weekday,
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Date in the proleptic gregorian calendar for a number of days since 1970-01-01
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Number of days since 1970-01-01 for a date in the proleptic gregorian calendar
+/// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Sun, 06 Nov 1994 08:49:37 GMT, the example of the rfc
+    fn example() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(784111777)
+    }
+
+    #[test]
+    fn the_three_formats_are_parsed() {
+        for date in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(date), Some(example()), "{}", date);
+        }
+    }
+
+    #[test]
+    fn two_digit_years_are_between_1970_and_2069() {
+        assert_eq!(
+            parse_http_date("Thursday, 01-Jan-70 00:00:00 GMT"),
+            Some(UNIX_EPOCH)
+        );
+        assert_eq!(
+            parse_http_date("Sunday, 31-Dec-69 00:00:00 GMT").map(format_http_date),
+            Some("Tue, 31 Dec 2069 00:00:00 GMT".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_dates_are_rejected() {
+        for date in [
+            "",
+            "yesterday",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:-1:37 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            // NOTE: before the epoch, a SystemTime can not be built from it
+            "Wed, 31 Dec 1969 23:59:59 GMT",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{}", date);
+        }
+    }
+
+    #[test]
+    fn huge_years_are_rejected_without_overflowing() {
+        for date in [
+            "Sun, 06 Nov 10000 08:49:37 GMT",
+            "Sun, 06 Nov 10000000000000 08:49:37 GMT",
+            "Sun, 06 Nov 9223372036854775807 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 10000000000000",
+            "Sunday, 06-Nov-9223372036854775807 08:49:37 GMT",
+            "Sunday, 06-Nov--9223372036854775808 08:49:37 GMT",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{}", date);
+        }
+        assert!(parse_http_date("Fri, 31 Dec 9999 23:59:59 GMT").is_some());
+    }
+
+    #[test]
+    fn formatted_dates_are_parsed_back() {
+        assert_eq!(format_http_date(example()), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            parse_http_date(&format_http_date(example())),
+            Some(example())
+        );
+    }
+}
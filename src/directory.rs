@@ -0,0 +1,34 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+/// Directory served under /files. It is shared with the handlers so that it can be
+/// swapped while the server runs, see POST /_reload
+#[derive(Debug, Clone, Default)]
+pub struct Directory {
+    path: Arc<RwLock<PathBuf>>,
+}
+
+impl Directory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Arc::new(RwLock::new(path.into())),
+        }
+    }
+
+    /// The path is cloned so that a request uses the same directory from start to end
+    pub fn path(&self) -> PathBuf {
+        self.path
+            .read()
+            .expect("Directory lock should not be poisoned")
+            .clone()
+    }
+
+    pub fn set(&self, path: PathBuf) {
+        *self
+            .path
+            .write()
+            .expect("Directory lock should not be poisoned") = path;
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Shared state of the draining mode used for zero-downtime deploys. Once started,
+/// /healthz returns 503 so that load balancers stop sending traffic, requests are
+/// still served and the server stops after the grace period
+#[derive(Debug, Clone, Default)]
+pub struct Drain {
+    started: Arc<Mutex<Option<Instant>>>,
+    /// set by stop to end the grace period right away
+    stopped: Arc<AtomicBool>,
+}
+
+impl Drain {
+    /// False if draining had already started, the grace period is not reset
+    pub fn start(&self) -> bool {
+        let mut started = self
+            .started
+            .lock()
+            .expect("Drain lock should not be poisoned");
+        match *started {
+            Some(_) => false,
+            None => {
+                *started = Some(Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Starts draining with no grace period, the server stops once the requests in
+    /// progress are completed
+    pub fn stop(&self) {
+        self.start();
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.started
+            .lock()
+            .expect("Drain lock should not be poisoned")
+            .is_some()
+    }
+
+    pub fn is_over(&self, grace_period: Duration) -> bool {
+        if self.stopped.load(Ordering::SeqCst) {
+            return true;
+        }
+        self.started
+            .lock()
+            .expect("Drain lock should not be poisoned")
+            .is_some_and(|x| x.elapsed() >= grace_period)
+    }
+}
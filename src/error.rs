@@ -1,19 +1,116 @@
 use thiserror::Error;
 
+use crate::http::HttpStatus;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Invalid request line, expected method, path and http version, got {0}")]
     InvalidRequestLine(String),
 
+    #[error("Invalid request line, non ascii characters must be percent-encoded")]
+    NonAsciiRequestLine,
+
+    #[error("Invalid request target {0} for this method")]
+    InvalidRequestTarget(String),
+
+    #[error("Connection closed before the request line")]
+    ConnectionClosed,
+
+    #[error("Request not received before the request read timeout")]
+    RequestReadTimeout,
+
     #[error("Invalid request line, missing CRLF")]
     MissingCRLFFromLine,
 
+    #[error("Invalid chunk in chunked body")]
+    InvalidChunk,
+
+    #[error("Invalid Content-Length {0}, expected digits only")]
+    InvalidContentLength(String),
+
     #[error("Invalid http header")]
     InvalidHeader,
 
+    #[error("Unsupported transfer coding {0} in TE header")]
+    UnsupportedTransferCoding(String),
+
+    #[error("Unsupported expectation {0} in Expect header")]
+    UnsupportedExpectation(String),
+
+    #[error("Request body of {0} bytes exceeds the maximum body size")]
+    PayloadTooLarge(u64),
+
+    #[error("Request line exceeds the maximum length")]
+    RequestLineTooLong,
+
+    #[error("Header line exceeds the maximum length (header: {0:?})")]
+    HeaderLineTooLong(Option<String>),
+
+    #[error("Request with {0} header or trailer fields exceeds the maximum")]
+    TooManyHeaders(usize),
+
+    #[error("Request path of {0} segments exceeds the maximum depth")]
+    PathTooDeep(usize),
+
+    #[error("Query string of {0} bytes exceeds the maximum length")]
+    QueryTooLong(usize),
+
+    #[error("Query string with {0} parameters exceeds the maximum")]
+    TooManyQueryParams(usize),
+
+    #[error("Unsupported media type {0:?}")]
+    UnsupportedMediaType(String),
+
+    #[error("Invalid buffer size {0}, must be at least {min}", min = crate::config::MIN_BUFFER_SIZE)]
+    InvalidBufferSize(usize),
+
+    #[error("Invalid compression level {0}, must be between 0 and 9")]
+    InvalidCompressionLevel(u32),
+
+    #[error("Invalid failure rate {0}, must be between 0 and 1")]
+    InvalidFailureRate(f64),
+
+    #[error("Unsupported content coding {0}, expected gzip or deflate")]
+    UnsupportedContentCoding(String),
+
+    #[error("Invalid duration {0}, expected for ex 500ms, 30s or 2m")]
+    InvalidDuration(String),
+
+    #[error("Invalid file mode {0}, expected octal permissions such as 0644")]
+    InvalidFileMode(String),
+
+    #[error("Handler panicked: {0}")]
+    HandlerPanicked(String),
+
     #[error("Invalid pool size")]
     InvalidPoolSize,
 
+    #[error("Invalid base url {0}, expected http(s)://host[:port]")]
+    InvalidBaseUrl(String),
+
+    #[error("No port to listen on")]
+    NoPort,
+
+    #[error("Invalid header {0}, expected Key: value")]
+    InvalidHeaderArg(String),
+
+    #[error("Invalid acl rule, expected METHOD:/path/prefix or CONNECT:host[:port], got {0}")]
+    InvalidAclRule(String),
+
+    #[error(
+        "Invalid route pattern {0}, expected a leading slash and a wildcard only as last segment"
+    )]
+    InvalidRoutePattern(String),
+
+    #[error("Missing value for argument {0}")]
+    MissingArgValue(String),
+
+    #[error("Unknown argument {0}")]
+    UnknownArg(String),
+
+    #[error(transparent)]
+    Http(#[from] HttpError),
+
     #[error("Can not compress")]
     CanNotCompress,
 
@@ -23,10 +120,59 @@ pub enum Error {
     #[error(transparent)]
     StrumParseError(#[from] strum::ParseError),
 
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    FormError(#[from] serde_urlencoded::de::Error),
+
+    #[error(transparent)]
+    TomlError(#[from] toml::de::Error),
+
     #[error(transparent)]
     ParseIntError(#[from] std::num::ParseIntError),
 
+    #[error(transparent)]
+    ParseFloatError(#[from] std::num::ParseFloatError),
+
     #[error(transparent)]
     FromUtf8Error(#[from] std::string::FromUtf8Error),
 }
 pub type Result<T> = core::result::Result<T, Error>;
+
+/// Lets a handler stop with a given status, for ex with `Err(HttpError::bad_request(..))?`.
+/// The message, if any, is sent as a text/plain body
+#[derive(Debug, Clone, Error)]
+#[error("{} {}", status.as_ref(), message.as_deref().unwrap_or_default())]
+pub struct HttpError {
+    pub status: HttpStatus,
+    pub message: Option<String>,
+}
+
+impl HttpError {
+    pub fn new(status: HttpStatus) -> Self {
+        Self {
+            status,
+            message: None,
+        }
+    }
+
+    pub fn with_message(status: HttpStatus, message: &str) -> Self {
+        Self {
+            status,
+            message: Some(message.to_string()),
+        }
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        Self::with_message(HttpStatus::BadRequest400, message)
+    }
+
+    pub fn forbidden() -> Self {
+        Self::new(HttpStatus::Forbidden403)
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(HttpStatus::NotFound404)
+    }
+}
@@ -0,0 +1,173 @@
+use std::{fmt, fs::Metadata, time::UNIX_EPOCH};
+
+/// Entity tag of a representation, sent in ETag and compared with the conditional
+/// headers. A weak tag only says that two representations are equivalent, not that
+/// their bytes are identical
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-8.8.3
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityTag {
+    pub weak: bool,
+    /// opaque value, without the quotes
+    pub tag: String,
+}
+
+impl EntityTag {
+    pub fn strong(tag: &str) -> Self {
+        Self {
+            weak: false,
+            tag: tag.to_string(),
+        }
+    }
+
+    /// Tag of a file, from its length and modification time. None if the platform
+    /// does not give the modification time
+    pub fn from_metadata(metadata: &Metadata) -> Option<Self> {
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self::strong(&format!(
+            "{:x}-{:x}",
+            modified.as_nanos(),
+            metadata.len()
+        )))
+    }
+
+    /// Parses "tag" or W/"tag", None if the value is not a valid entity tag
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (weak, quoted) = match s.strip_prefix("W/") {
+            Some(x) => (true, x),
+            None => (false, s),
+        };
+        let tag = quoted.strip_prefix('"')?.strip_suffix('"')?;
+        // NOTE: etagc excludes the double quote, spaces and controls
+        if tag.bytes().any(|x| x == b'"' || x <= b' ' || x == 0x7f) {
+            return None;
+        }
+        Some(Self {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+
+    /// Both tags are strong and identical, used by If-Match and If-Range
+    pub fn strong_eq(&self, other: &Self) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// The tags are identical whether they are weak or not, used by If-None-Match
+    pub fn weak_eq(&self, other: &Self) -> bool {
+        self.tag == other.tag
+    }
+
+    /// Same tag marked as weak, for ex once the representation is compressed
+    pub fn into_weak(self) -> Self {
+        Self { weak: true, ..self }
+    }
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.weak {
+            true => write!(f, "W/\"{}\"", self.tag),
+            false => write!(f, "\"{}\"", self.tag),
+        }
+    }
+}
+
+/// How tags are compared, see EntityTag::strong_eq and EntityTag::weak_eq
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Strong,
+    Weak,
+}
+
+/// Evaluates an If-Match or If-None-Match header, * or a list of tags, against the
+/// current tag of the resource. None as current means the resource does not exist, it
+/// matches nothing, not even *
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-13.1.1
+pub fn matches(header: &str, current: Option<&EntityTag>, comparison: Comparison) -> bool {
+    let Some(current) = current else {
+        return false;
+    };
+    if header.trim() == "*" {
+        return true;
+    }
+    // NOTE: a tag containing a comma is not supported, the server never generates one
+    header
+        .split(',')
+        .filter_map(EntityTag::parse)
+        .any(|x| match comparison {
+            Comparison::Strong => x.strong_eq(current),
+            Comparison::Weak => x.weak_eq(current),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn tags_are_parsed_and_displayed_with_their_weakness() {
+        for s in ["\"abc\"", "W/\"abc\"", "\"\""] {
+            assert_eq!(EntityTag::parse(s).unwrap().to_string(), s);
+        }
+        assert_eq!(
+            EntityTag::parse(" W/\"abc\" "),
+            Some(EntityTag::strong("abc").into_weak())
+        );
+        for invalid in ["abc", "\"abc", "w/\"abc\"", "W/abc", "\"a b\"", "\"a\"b\""] {
+            assert_eq!(EntityTag::parse(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn weak_tags_only_match_under_the_weak_comparison() {
+        let strong = EntityTag::strong("abc");
+        let weak = EntityTag::strong("abc").into_weak();
+        let other = EntityTag::strong("xyz");
+
+        assert!(strong.strong_eq(&strong));
+        assert!(!weak.strong_eq(&strong));
+        assert!(!strong.strong_eq(&weak));
+        assert!(!weak.strong_eq(&weak));
+        assert!(!strong.strong_eq(&other));
+
+        assert!(weak.weak_eq(&strong));
+        assert!(strong.weak_eq(&weak));
+        assert!(weak.weak_eq(&weak));
+        assert!(!weak.weak_eq(&other));
+    }
+
+    #[test]
+    fn header_lists_and_star_are_matched_against_the_current_tag() {
+        let strong = EntityTag::strong("abc");
+        let weak = EntityTag::strong("abc").into_weak();
+        let table = [
+            ("\"abc\"", Some(&strong), true, true),
+            ("W/\"abc\"", Some(&strong), false, true),
+            ("\"abc\"", Some(&weak), false, true),
+            ("\"x\", W/\"y\" , \"abc\"", Some(&strong), true, true),
+            ("\"x\", invalid", Some(&strong), false, false),
+            ("*", Some(&weak), true, true),
+            // NOTE: a missing resource matches nothing, not even *
+            ("*", None, false, false),
+            ("\"abc\"", None, false, false),
+        ];
+        for (header, current, strong_match, weak_match) in table {
+            assert_eq!(
+                matches(header, current, Comparison::Strong),
+                strong_match,
+                "{} {:?}",
+                header,
+                current
+            );
+            assert_eq!(
+                matches(header, current, Comparison::Weak),
+                weak_match,
+                "{} {:?}",
+                header,
+                current
+            );
+        }
+    }
+}
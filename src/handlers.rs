@@ -0,0 +1,797 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use serde_json::{map::Entry, Map, Value};
+
+use crate::body::ProgressReader;
+use crate::cgi;
+use crate::config::{ConcurrencyMode, Config};
+use crate::date::{format_http_date, parse_http_date};
+use crate::etag::{self, Comparison, EntityTag};
+use crate::http::{
+    decode_body, negotiate_encoding, percent_decode_path, HttpMethod, HttpRequest, HttpResponse,
+    HttpStatus,
+};
+use crate::mime;
+use crate::range::{parse_range, ByteRange};
+use crate::router::Router;
+use crate::sse;
+use crate::uploads::Upload;
+use crate::{HttpError, Result};
+
+/// Files above this size are streamed instead of being read in memory
+const STREAM_THRESHOLD: u64 = 1024 * 1024;
+/// Largest payload returned by /_bench
+const MAX_BENCH_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Router with all the built-in routes
+pub fn router() -> Result<Router> {
+    let mut router = Router::new();
+    router
+        .route(HttpMethod::Get, "/", root)?
+        .route(HttpMethod::Get, "/echo/*message", echo)?
+        .route(HttpMethod::Get, "/user-agent", user_agent)?
+        .route(HttpMethod::Get, "/healthz", healthz)?
+        .route(HttpMethod::Get, "/events", events)?
+        .route(HttpMethod::Post, "/_drain", drain)?
+        .route(HttpMethod::Post, "/_reload", reload)?
+        .route(HttpMethod::Post, "/_shutdown", shutdown)?
+        .route(HttpMethod::Get, "/_debug/echo", debug_echo)?
+        .route(HttpMethod::Post, "/_debug/echo", debug_echo)?
+        .route(HttpMethod::Get, "/_echo_headers", echo_headers)?
+        .route(HttpMethod::Get, "/_bench", bench)?
+        .route(HttpMethod::Get, "/files/*filename", get_file)?
+        .route(HttpMethod::Post, "/files/*filename", post_file)?
+        .route(HttpMethod::Put, "/files/*filename", put_file)?
+        .route(HttpMethod::Delete, "/files/*filename", delete_file)?
+        .route(HttpMethod::Get, "/cgi/*script", cgi::run)?
+        .route(HttpMethod::Post, "/cgi/*script", cgi::run)?;
+    Ok(router)
+}
+
+fn root(_request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    match &config.root_file {
+        None if config.landing_page => Ok(HttpResponse::content_response(
+            &landing_page(config),
+            "text/html",
+        )),
+        None => Ok(HttpResponse::empty_response(HttpStatus::Ok200)),
+        Some(root_file) => {
+            let content = std::fs::read(root_file)?;
+            let content_type =
+                content_type(root_file, &content, config).unwrap_or("application/octet-stream");
+            Ok(HttpResponse::bytes_response(content, content_type))
+        }
+    }
+}
+
+/// Escapes the characters that have a meaning in html text and attributes
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Small html page with the server version, the registered routes and the uptime
+fn landing_page(config: &Config) -> String {
+    let routes: String = config
+        .router
+        .routes()
+        .map(|(method, pattern)| {
+            format!(
+                "<li><code>{} {}</code></li>\n",
+                method.as_ref(),
+                html_escape(pattern)
+            )
+        })
+        .collect();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>{name}</title></head>\n<body>\n\
+         <h1>{name} {version}</h1>\n<p>Up for {uptime}s</p>\n<ul>\n{routes}</ul>\n\
+         </body>\n</html>\n",
+        name = env!("CARGO_PKG_NAME"),
+        version = env!("CARGO_PKG_VERSION"),
+        uptime = config.started.elapsed().as_secs(),
+        routes = routes,
+    )
+}
+
+/// Content type from the extension, falling back on the content when sniffing is enabled
+fn content_type(path: &Path, content: &[u8], config: &Config) -> Option<&'static str> {
+    mime::from_extension(path).or_else(|| match config.sniff_content_type {
+        true => mime::sniff_content_type(&content[..content.len().min(512)]),
+        false => None,
+    })
+}
+
+/// Files are served as application/octet-stream unless sniffing is enabled
+fn file_content_type(path: &Path, content: &[u8], config: &Config) -> &'static str {
+    let content_type = match config.sniff_content_type {
+        true => content_type(path, content, config),
+        false => None,
+    };
+    content_type.unwrap_or("application/octet-stream")
+}
+
+/// True if the streamed response of the file would be compressed, see
+/// HttpResponse::add_compression. Only the first bytes are read, for the content type
+fn is_compressed(request: &HttpRequest, path: &Path, config: &Config) -> Result<bool> {
+    let coding = request
+        .header("accept-encoding")
+        .and_then(|x| negotiate_encoding(x, &config.encoding_priority));
+    if coding.is_none() {
+        return Ok(false);
+    }
+    let mut start = Vec::new();
+    File::open(path)?.take(512).read_to_end(&mut start)?;
+    let content_type = file_content_type(path, &start, config);
+    Ok(mime::is_compressible(
+        content_type,
+        &config.compressible_types,
+    ))
+}
+
+/// Escapes a string and wraps it in quotes so it is a valid json string
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Json if the client prefers it over plain text, plain text otherwise
+fn echo(request: &mut HttpRequest, _config: &Config) -> Result<HttpResponse> {
+    let echo = &request.params["message"];
+    let json = request.accepts("application/json")
+        && request.accept_quality("application/json") > request.accept_quality("text/plain");
+    let mut response = match json {
+        true => HttpResponse::content_response(
+            &format!("{{\"message\":{}}}", json_string(echo)),
+            "application/json",
+        ),
+        false => HttpResponse::content_response(echo, "text/plain"),
+    };
+    response.append_header("Vary", "Accept");
+    response.set_header("Accept-Ranges", "none");
+    Ok(response)
+}
+
+/// Returns size zero bytes, for load testing the server itself. The payload is
+/// generated while it is written so that a big size does not use memory
+fn bench(request: &mut HttpRequest, _config: &Config) -> Result<HttpResponse> {
+    let size: u64 = match request.query.get("size").map(|x| x.parse()) {
+        Some(Ok(size)) => size,
+        _ => Err(HttpError::bad_request("Expected a size in bytes"))?,
+    };
+    if size > MAX_BENCH_SIZE {
+        Err(HttpError::bad_request(&format!(
+            "Size exceeds the maximum of {} bytes",
+            MAX_BENCH_SIZE
+        )))?;
+    }
+    Ok(HttpResponse::sized_stream_response(
+        io::repeat(0),
+        size,
+        "application/octet-stream",
+    ))
+}
+
+/// Sends the number of seconds since the stream started, every second, until the client
+/// disconnects. The stream also ends when the server drains so that it does not hold
+/// its worker forever. A 503 is sent once max_event_streams are open
+fn events(_request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    let max = match config.mode {
+        // NOTE: with as many streams as workers, no other request would ever be handled
+        ConcurrencyMode::Pool => {
+            let max = config.threads.saturating_sub(1);
+            config.max_event_streams.map_or(max, |x| x.min(max))
+        }
+        // NOTE: each connection has its own thread, a stream does not take one from
+        // the other requests
+        ConcurrencyMode::ThreadPerConnection => config.max_event_streams.unwrap_or(usize::MAX),
+    };
+    let Some(stream) = config.event_streams.start(max) else {
+        let mut response = HttpResponse::empty_response(HttpStatus::ServiceUnavailable503);
+        let retry_after = config
+            .retry_after_format
+            .format(config.retry_after, SystemTime::now());
+        response.set_header("Retry-After", &retry_after);
+        return Ok(response);
+    };
+    let drain = config.drain.clone();
+    Ok(sse::event_stream(move |events| {
+        let _stream = stream;
+        let mut seconds: u64 = 0;
+        while !drain.is_draining() {
+            events.send(&seconds.to_string())?;
+            thread::sleep(Duration::from_secs(1));
+            seconds += 1;
+        }
+        Ok(())
+    }))
+}
+
+fn user_agent(request: &mut HttpRequest, _config: &Config) -> Result<HttpResponse> {
+    match request.header("user-agent") {
+        None => Ok(HttpResponse::not_found()),
+        Some(user_agent) => Ok(HttpResponse::content_response(user_agent, "text/plain")),
+    }
+}
+
+/// 503 once draining started so that load balancers stop sending traffic
+fn healthz(_request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    match config.drain.is_draining() {
+        true => {
+            let mut response = HttpResponse::static_response("draining", "text/plain");
+            response.status = HttpStatus::ServiceUnavailable503;
+            Ok(response)
+        }
+        false => Ok(HttpResponse::static_response("ok", "text/plain")),
+    }
+}
+
+/// Starts draining, the server stops after the grace period. Only local clients can
+/// call it, see check_local
+fn drain(request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    check_local(request, config)?;
+    if config.drain.start() {
+        println!("Draining, shutting down in {:?}", config.drain_grace_period);
+    }
+    Ok(HttpResponse::empty_response(HttpStatus::Accepted202))
+}
+
+/// Stops the server once the requests in progress are completed, for ex at the end of a
+/// test. The route does not exist without --admin-token
+fn shutdown(request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    let Some(admin_token) = &config.admin_token else {
+        Err(HttpError::not_found())?
+    };
+    check_admin_token(request, admin_token)?;
+    println!("Shutting down on request of {:?}", request.peer_addr);
+    config.drain.stop();
+    Ok(HttpResponse::empty_response(HttpStatus::Accepted202))
+}
+
+/// Forbids the requests not sending the token in X-Admin-Token
+fn check_admin_token(request: &HttpRequest, admin_token: &str) -> Result<()> {
+    if request.header("x-admin-token") != Some(admin_token) {
+        Err(HttpError::forbidden())?;
+    }
+    Ok(())
+}
+
+/// Forbids the clients of other hosts. When --admin-token is set, local clients must
+/// send it as well: behind a reverse proxy on the same host, every client is local
+fn check_local(request: &HttpRequest, config: &Config) -> Result<()> {
+    // NOTE: clients of the unix socket are local
+    let local = match request.peer_addr {
+        Some(peer_addr) => peer_addr.ip().is_loopback(),
+        None => true,
+    };
+    if !local {
+        Err(HttpError::forbidden())?;
+    }
+    match &config.admin_token {
+        Some(admin_token) => check_admin_token(request, admin_token),
+        None => Ok(()),
+    }
+}
+
+/// Swaps the directory served under /files, for ex after a deploy. Only local clients
+/// can call it, see check_local. Requests in progress keep the previous directory
+fn reload(request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    check_local(request, config)?;
+    let Some(directory) = request.query.get("directory").map(PathBuf::from) else {
+        Err(HttpError::bad_request("Missing directory parameter"))?
+    };
+    if !directory.is_dir() {
+        Err(HttpError::bad_request("Not a directory"))?;
+    }
+    println!("Serving directory {}", directory.display());
+    config.directory.set(directory);
+    Ok(HttpResponse::no_content())
+}
+
+fn debug_echo(request: &mut HttpRequest, _config: &Config) -> Result<HttpResponse> {
+    let mut raw: Vec<u8> = (&*request).into();
+    request.body_reader().read_to_end(&mut raw)?;
+    Ok(HttpResponse::content_response(
+        &String::from_utf8_lossy(&raw),
+        "text/plain",
+    ))
+}
+
+/// Headers as parsed by the server, as a json object. Names are lowercased and the
+/// values of a repeated header are grouped in an array, in the order they were received
+fn echo_headers(request: &mut HttpRequest, _config: &Config) -> Result<HttpResponse> {
+    let mut headers = Map::new();
+    for header in request.headers.iter() {
+        let value = Value::String(header.value.clone());
+        match headers.entry(header.key.to_lowercase()) {
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+            }
+            Entry::Occupied(mut entry) => match entry.get_mut() {
+                Value::Array(values) => values.push(value),
+                first => *first = Value::Array(vec![first.take(), value]),
+            },
+        }
+    }
+    Ok(HttpResponse::content_response(
+        &serde_json::to_string(&headers)?,
+        "application/json",
+    ))
+}
+
+/// Content-Disposition value telling the browser to download the file instead of rendering it.
+/// The filename is sent as a quoted-string so quotes and backslashes must be escaped. A
+/// non-ASCII name is also sent as filename*, the quoted one being its ASCII fallback
+/// https://datatracker.ietf.org/doc/html/rfc6266#section-4.3
+fn attachment_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .filter(|c| !c.is_control())
+        .flat_map(|c| match c {
+            '"' | '\\' => vec!['\\', c],
+            c if !c.is_ascii() => vec!['_'],
+            c => vec![c],
+        })
+        .collect();
+    if filename.is_ascii() {
+        return format!("attachment; filename=\"{}\"", fallback);
+    }
+    // NOTE: attr-char of https://datatracker.ietf.org/doc/html/rfc5987#section-3.2.1,
+    // the other bytes of the UTF-8 name are percent-encoded
+    let encoded: String = filename
+        .bytes()
+        .map(|x| match x {
+            x if x.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&x) => {
+                (x as char).to_string()
+            }
+            x => format!("%{:02X}", x),
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+/// Path of the file in the served directory. The error is the status to respond with:
+/// 403 if the path escapes the directory, 404 if the file or one of its parent
+/// directories is hidden and hidden files are not served, 403 if it goes through a
+/// symlink and symlinks are not followed
+fn file_path(filename: &str, config: &Config) -> std::result::Result<PathBuf, HttpStatus> {
+    if filename.split('/').any(|segment| segment == "..") {
+        return Err(HttpStatus::Forbidden403);
+    }
+    let hidden = filename.split('/').any(|segment| segment.starts_with('.'));
+    if hidden && !config.serve_hidden {
+        return Err(HttpStatus::NotFound404);
+    }
+    let directory = config.directory.path();
+    if !config.follow_symlinks && has_symlink(&directory, filename) {
+        return Err(HttpStatus::Forbidden403);
+    }
+    Ok(PathBuf::from(&format!(
+        "{}/{}",
+        directory.display(),
+        filename
+    )))
+}
+
+/// True if a component of the path under the served directory is a symlink, which
+/// could point outside of it. Components that do not exist, for ex a file about to be
+/// uploaded, are not symlinks
+fn has_symlink(directory: &Path, filename: &str) -> bool {
+    let mut path = directory.to_path_buf();
+    filename
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .any(|segment| {
+            path.push(segment);
+            path.symlink_metadata()
+                .is_ok_and(|x| x.file_type().is_symlink())
+        })
+}
+
+/// Cache-Control and Expires from --cache-max-age, 0 forbids caching. Expires is only
+/// used by http/1.0 caches, Cache-Control takes precedence
+/// https://datatracker.ietf.org/doc/html/rfc9111#section-5.3
+fn add_cache_headers(response: &mut HttpResponse, config: &Config) {
+    match config.cache_max_age {
+        None => {}
+        Some(0) => response.set_header("Cache-Control", "no-store"),
+        Some(max_age) => {
+            response.set_header("Cache-Control", &format!("public, max-age={}", max_age));
+            let expires = SystemTime::now() + Duration::from_secs(max_age);
+            response.set_header("Expires", &format_http_date(expires));
+        }
+    }
+}
+
+fn get_file(request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    let filename = &request.params["filename"];
+    let filepath = match file_path(filename, config) {
+        Ok(filepath) => filepath,
+        Err(status) => return Ok(HttpResponse::empty_response(status)),
+    };
+
+    // NOTE: without the trailing slash, relative links in the directory would not
+    // resolve. When trailing slashes are stripped, redirecting would loop forever
+    if filepath.is_dir()
+        && !request.path.ends_with('/')
+        && !config.parse_options.strip_trailing_slash
+    {
+        return Ok(HttpResponse::redirect_response(&absolute_url(
+            request,
+            config,
+            &format!("{}/", request.path),
+        )));
+    }
+
+    match filepath.is_file() || filepath.join("index.html").is_file() {
+        true => {
+            let index = filepath.is_dir();
+            if index
+                && !config.follow_symlinks
+                && has_symlink(
+                    &config.directory.path(),
+                    &format!("{}/index.html", filename),
+                )
+            {
+                return Ok(HttpResponse::empty_response(HttpStatus::Forbidden403));
+            }
+            let filepath = match index {
+                true => filepath.join("index.html"),
+                false => filepath,
+            };
+            let download = request.query.get("download").is_some_and(|x| x == "1");
+            let metadata = filepath.metadata()?;
+            let len = metadata.len();
+            let etag = EntityTag::from_metadata(&metadata);
+            if request
+                .header("if-none-match")
+                .is_some_and(|x| etag::matches(x, etag.as_ref(), Comparison::Weak))
+            {
+                let mut response = HttpResponse::empty_response(HttpStatus::NotModified304);
+                if let Some(etag) = &etag {
+                    // NOTE: the tag the 200 would have, weak when its stream is compressed
+                    let etag = match len > STREAM_THRESHOLD
+                        && is_compressed(request, &filepath, config)?
+                    {
+                        true => etag.clone().into_weak(),
+                        false => etag.clone(),
+                    };
+                    response.set_header("ETag", &etag.to_string());
+                }
+                add_cache_headers(&mut response, config);
+                return Ok(response);
+            }
+            let range = request
+                .header("range")
+                .filter(|_| range_applies(request, etag.as_ref()))
+                .and_then(|x| parse_range(x, len));
+            let range = match range {
+                None => None,
+                Some(ByteRange::Satisfiable(range)) => Some(range),
+                Some(ByteRange::Unsatisfiable) => {
+                    let mut response =
+                        HttpResponse::empty_response(HttpStatus::RangeNotSatisfiable416);
+                    response.set_header("Content-Range", &format!("bytes */{}", len));
+                    return Ok(response);
+                }
+            };
+            let mut response = match len > STREAM_THRESHOLD {
+                true => {
+                    let mut file = File::open(&filepath)?;
+                    let mut start = Vec::new();
+                    (&mut file).take(512).read_to_end(&mut start)?;
+                    let content_type = file_content_type(&filepath, &start, config);
+                    // NOTE: the length is known, so the connection can be kept alive
+                    // even for http/1.0 clients
+                    match &range {
+                        None => {
+                            file.rewind()?;
+                            HttpResponse::sized_stream_response(file, len, content_type)
+                        }
+                        Some(range) => {
+                            file.seek(SeekFrom::Start(range.start))?;
+                            let length = range.end - range.start;
+                            HttpResponse::sized_stream_response(file, length, content_type)
+                        }
+                    }
+                }
+                false => {
+                    let content = std::fs::read(&filepath)?;
+                    let content_type = file_content_type(&filepath, &content, config);
+                    let content = match &range {
+                        None => content,
+                        Some(range) => content[range.start as usize..range.end as usize].to_vec(),
+                    };
+                    HttpResponse::bytes_response(content, content_type)
+                }
+            };
+            response.set_header("Accept-Ranges", "bytes");
+            if let Some(etag) = &etag {
+                response.set_header("ETag", &etag.to_string());
+            }
+            add_cache_headers(&mut response, config);
+            if let Some(range) = range {
+                response.status = HttpStatus::PartialContent206;
+                let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, len);
+                response.set_header("Content-Range", &content_range);
+            }
+            // NOTE: tells caches which resource was actually served
+            // https://datatracker.ietf.org/doc/html/rfc9110#section-8.7
+            if index {
+                let path = format!("{}/index.html", request.path.trim_end_matches('/'));
+                response.set_header("Content-Location", &absolute_url(request, config, &path));
+            }
+            if download {
+                // NOTE: the name of the served file, the index of a directory, decoded
+                // since the route parameter is still percent-encoded
+                let name = filepath.file_name().unwrap_or_default().to_string_lossy();
+                let disposition = attachment_disposition(&percent_decode_path(&name));
+                response.set_header("Content-Disposition", &disposition);
+            }
+            Ok(response)
+        }
+        false => Ok(HttpResponse::not_found()),
+    }
+}
+
+/// Body of an upload, decoded if it was sent with content codings. It is read while
+/// being written to disk so that a large upload is never fully in memory
+fn upload_body<'r>(
+    request: &'r mut HttpRequest<'_>,
+    config: &Config,
+) -> Result<Box<dyn Read + 'r>> {
+    let codings = request.content_codings()?;
+    let label = format!("upload of {}", request.path);
+    let body: Box<dyn Read + 'r> = match config.verbose {
+        true => Box::new(
+            ProgressReader::new(request.body_reader(), &label, config.progress_interval)
+                .with_hook(config.on_progress.clone()),
+        ),
+        false => Box::new(request.body_reader()),
+    };
+    // NOTE: the stored file is the original content, not the encoded stream
+    Ok(decode_body(body, &codings))
+}
+
+/// Writes an uploaded file. The body goes to a temporary file of the same directory
+/// which is then renamed, so that a failed upload does not leave a truncated file and
+/// readers see either the previous file or the new one. See Config::sync_uploads for
+/// crashes
+fn write_file(filepath: &Path, body: &mut dyn Read, config: &Config) -> Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let filename = filepath.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = filepath.with_file_name(format!(
+        ".{}.{}.upload",
+        filename,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    match copy_to_file(&temporary, body, config) {
+        Ok(()) => std::fs::rename(&temporary, filepath)?,
+        Err(e) => {
+            let _ = std::fs::remove_file(&temporary);
+            Err(e)?
+        }
+    }
+    Ok(())
+}
+
+/// The permissions are set explicitly so that they do not depend on the umask
+fn copy_to_file(filepath: &Path, body: &mut dyn Read, config: &Config) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(filepath)?;
+    #[cfg(unix)]
+    if let Some(mode) = config.upload_mode {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+    }
+    io::copy(body, &mut file)?;
+    file.flush()?;
+    // NOTE: without it, the rename may reach the disk before the content
+    if config.sync_uploads {
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Counts the upload against the limit of the client, None if the limit is reached.
+/// Clients connected over a unix socket have no ip and are not limited
+fn start_upload(request: &HttpRequest, config: &Config) -> Option<Option<Upload>> {
+    match (config.max_uploads_per_client, request.peer_addr) {
+        (Some(max), Some(peer_addr)) => config.uploads.start(peer_addr.ip(), max).map(Some),
+        _ => Some(None),
+    }
+}
+
+/// Url of the path as seen by the client. The path is left relative when the base url
+/// is unknown, which clients resolve against the request url
+fn absolute_url(request: &HttpRequest, config: &Config, path: &str) -> String {
+    let base_url = match &config.base_url {
+        Some(x) => Some(x.clone()),
+        None => request.base_url(config.behind_proxy),
+    };
+    match base_url {
+        Some(base_url) => format!("{}{}", base_url, path),
+        None => path.to_string(),
+    }
+}
+
+/// 429 telling the client when to retry its upload
+fn too_many_uploads(config: &Config) -> HttpResponse {
+    let mut response = HttpResponse::empty_response(HttpStatus::TooManyRequests429);
+    let retry_after = config
+        .retry_after_format
+        .format(config.retry_after, SystemTime::now());
+    response.set_header("Retry-After", &retry_after);
+    response
+}
+
+/// 201 with the Location of the created file, 204 if an existing file was replaced
+fn post_file(request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    let Some(_upload) = start_upload(request, config) else {
+        return Ok(too_many_uploads(config));
+    };
+    let filename = &request.params["filename"];
+    let filepath = match file_path(filename, config) {
+        Ok(filepath) => filepath,
+        Err(status) => return Ok(HttpResponse::empty_response(status)),
+    };
+
+    let dirpath = filepath.parent().expect("Directory should not be none");
+    match dirpath.exists() {
+        true => {
+            if request.body_reader().is_done() {
+                Err(HttpError::bad_request("Missing body"))?;
+            }
+
+            let existed = filepath.exists();
+            write_file(&filepath, &mut upload_body(request, config)?, config)?;
+
+            match existed {
+                true => Ok(HttpResponse::no_content()),
+                false => {
+                    let mut response = HttpResponse::created();
+                    response.set_header("Location", &absolute_url(request, config, &request.path));
+                    Ok(response)
+                }
+            }
+        }
+
+        false => Ok(HttpResponse::not_found()),
+    }
+}
+
+/// Evaluates If-Range, the range is ignored and the full file sent if the file changed.
+/// Only a strong tag validates it: the server sends no Last-Modified so a date can not
+/// be compared
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-13.1.5
+fn range_applies(request: &HttpRequest, etag: Option<&EntityTag>) -> bool {
+    let Some(if_range) = request.header("if-range") else {
+        return true;
+    };
+    match (EntityTag::parse(if_range), etag) {
+        (Some(x), Some(etag)) => x.strong_eq(etag),
+        _ => false,
+    }
+}
+
+/// Evaluates the preconditions of a mutation, true if it can be performed. If-Match
+/// takes precedence over If-Unmodified-Since. If-None-Match: * only allows creating
+/// the file
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-13.2.2
+fn preconditions_hold(request: &HttpRequest, filepath: &Path) -> bool {
+    let etag = filepath
+        .metadata()
+        .ok()
+        .filter(|x| x.is_file())
+        .and_then(|x| EntityTag::from_metadata(&x));
+    let if_match = match request.header("if-match") {
+        Some(x) => etag::matches(x, etag.as_ref(), Comparison::Strong),
+        None => unmodified_since(request, filepath),
+    };
+    let if_none_match = request
+        .header("if-none-match")
+        .is_some_and(|x| etag::matches(x, etag.as_ref(), Comparison::Weak));
+    if_match && !if_none_match
+}
+
+/// Evaluates If-Unmodified-Since, true if the mutation can be performed.
+/// The header is ignored if the date is invalid or if the file does not exist
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-13.1.4
+fn unmodified_since(request: &HttpRequest, filepath: &Path) -> bool {
+    let Some(date) = request
+        .header("if-unmodified-since")
+        .and_then(parse_http_date)
+    else {
+        return true;
+    };
+    let Ok(modified) = filepath.metadata().and_then(|x| x.modified()) else {
+        return true;
+    };
+    // NOTE: http dates have a one second resolution
+    let modified = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let date = date
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    modified <= date
+}
+
+fn put_file(request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    let Some(_upload) = start_upload(request, config) else {
+        return Ok(too_many_uploads(config));
+    };
+    let filename = &request.params["filename"];
+    let filepath = match file_path(filename, config) {
+        Ok(filepath) => filepath,
+        Err(status) => return Ok(HttpResponse::empty_response(status)),
+    };
+
+    let dirpath = filepath.parent().expect("Directory should not be none");
+    if !dirpath.exists() || filepath.is_dir() {
+        return Ok(HttpResponse::not_found());
+    }
+    if !preconditions_hold(request, &filepath) {
+        return Ok(HttpResponse::empty_response(
+            HttpStatus::PreconditionFailed412,
+        ));
+    }
+
+    let existed = filepath.exists();
+    write_file(&filepath, &mut upload_body(request, config)?, config)?;
+
+    match existed {
+        true => Ok(HttpResponse::no_content()),
+        false => Ok(HttpResponse::created()),
+    }
+}
+
+/// 204 if the file was removed, 404 if there is no such file
+fn delete_file(request: &mut HttpRequest, config: &Config) -> Result<HttpResponse> {
+    let filename = &request.params["filename"];
+    let filepath = match file_path(filename, config) {
+        Ok(filepath) => filepath,
+        Err(status) => return Ok(HttpResponse::empty_response(status)),
+    };
+
+    if !filepath.is_file() {
+        return Ok(HttpResponse::not_found());
+    }
+    if !preconditions_hold(request, &filepath) {
+        return Ok(HttpResponse::empty_response(
+            HttpStatus::PreconditionFailed412,
+        ));
+    }
+
+    std::fs::remove_file(filepath)?;
+    Ok(HttpResponse::no_content())
+}
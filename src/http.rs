@@ -1,27 +1,181 @@
-use strum_macros::{AsRefStr, EnumString};
+use serde::de::DeserializeOwned;
+use strum::IntoEnumIterator;
+use strum_macros::{AsRefStr, EnumIter, EnumString};
 
-use crate::{Error, Result};
+use crate::body::{read_line_before, BodyReader, ChunkedWriter, RetryInterrupted};
+use crate::buffer_pool;
+use crate::etag::EntityTag;
+use crate::listener::Stream;
+use crate::mime::ContentType;
+use crate::{Error, HttpError, Result};
 use std::{
-    io::{BufRead, BufReader, Read, Write},
-    net::TcpStream,
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    io::{self, BufReader, Read, Write},
+    net::SocketAddr,
     str::FromStr,
+    time::{Duration, Instant},
 };
 
 #[derive(Debug)]
-pub struct HttpRequest {
+pub struct HttpRequest<'a> {
     pub method: HttpMethod,
+    pub target: RequestTarget,
     pub path: String,
+    pub query: HashMap<String, String>,
+    /// captured by the router from the route pattern
+    pub params: HashMap<String, String>,
     pub version: HttpVersion,
     pub headers: Vec<HttpHeader>,
-    pub body: Option<HttpBody>,
+    /// address of the client, None over a unix socket
+    pub peer_addr: Option<SocketAddr>,
+    /// the body is not read eagerly, see body_reader and read_body
+    body: BodyReader<'a>,
 }
 
-impl TryFrom<&mut BufReader<TcpStream>> for HttpRequest {
-    type Error = Error;
+/// The four forms of request-target
+/// https://datatracker.ietf.org/doc/html/rfc9112#section-3.2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestTarget {
+    /// /path?query, the usual form
+    Origin,
+    /// http://host:port/path?query, used when talking to a proxy
+    Absolute { scheme: String, authority: String },
+    /// host:port, only for CONNECT
+    Authority(String),
+    /// *, only for a server wide OPTIONS
+    Asterisk,
+}
+
+impl RequestTarget {
+    /// Returns the form along with the path and query part of the target.
+    /// For authority and asterisk forms, the whole target is returned
+    pub fn parse(target: &str) -> Result<(Self, &str)> {
+        let invalid = || Error::InvalidRequestTarget(target.to_string());
+
+        if target.starts_with('/') {
+            return Ok((Self::Origin, target));
+        }
+        if target == "*" {
+            return Ok((Self::Asterisk, target));
+        }
+        if let Some((scheme, rest)) = target.split_once("://") {
+            if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                Err(invalid())?;
+            }
+            let end = rest.find(['/', '?']).unwrap_or(rest.len());
+            let (authority, path_and_query) = rest.split_at(end);
+            if authority.is_empty() {
+                Err(invalid())?;
+            }
+            // NOTE: an empty path is equivalent to /
+            let path_and_query = match path_and_query.starts_with('/') {
+                true => path_and_query,
+                false => "/",
+            };
+            let target = Self::Absolute {
+                scheme: scheme.to_lowercase(),
+                authority: authority.to_string(),
+            };
+            return Ok((target, path_and_query));
+        }
+        match target.rsplit_once(':') {
+            Some((host, port))
+                if !host.is_empty()
+                    && !port.is_empty()
+                    && port.bytes().all(|x| x.is_ascii_digit()) =>
+            {
+                Ok((Self::Authority(target.to_string()), target))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// authority-form is reserved to CONNECT and asterisk-form to OPTIONS
+    pub fn allows(&self, method: &HttpMethod) -> bool {
+        match (self, method) {
+            (Self::Authority(_), HttpMethod::Connect) => true,
+            (Self::Authority(_), _) | (_, HttpMethod::Connect) => false,
+            (Self::Asterisk, method) => *method == HttpMethod::Options,
+            (Self::Origin | Self::Absolute { .. }, _) => true,
+        }
+    }
+}
+
+/// Limits and normalizations applied while reading a request from the stream
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    pub max_body_size: usize,
+    /// maximum length of the request line, including the CRLF
+    pub max_request_line_length: usize,
+    /// maximum length of a single header line, including the CRLF
+    pub max_header_line_length: usize,
+    /// maximum number of header fields, also applied to the trailer fields of a
+    /// chunked body
+    pub max_headers: usize,
+    /// collapse duplicate slashes, for ex //echo///hi becomes /echo/hi
+    pub merge_slashes: bool,
+    /// /echo/hi/ becomes /echo/hi, root path is left untouched
+    pub strip_trailing_slash: bool,
+    /// maximum number of segments of the path, counted after normalization and
+    /// percent-decoding
+    pub max_path_depth: usize,
+    /// maximum length of the query string, without the ?
+    pub max_query_length: usize,
+    /// maximum number of query parameters, repeated keys included
+    pub max_query_params: usize,
+    /// maximum time to receive the whole request, body included, counted from its first
+    /// byte. Unlike a timeout on each read, it is not reset by a client that keeps
+    /// sending a few bytes (slowloris)
+    pub request_read_timeout: Option<Duration>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_body_size: 10 * 1024 * 1024,
+            max_request_line_length: 8 * 1024,
+            max_header_line_length: 8 * 1024,
+            max_headers: 100,
+            merge_slashes: false,
+            strip_trailing_slash: false,
+            max_path_depth: 64,
+            max_query_length: 4 * 1024,
+            max_query_params: 100,
+            request_read_timeout: None,
+        }
+    }
+}
 
-    fn try_from(reader: &mut BufReader<TcpStream>) -> Result<Self> {
-        let mut s = String::new();
-        reader.read_line(&mut s)?;
+impl<'a> HttpRequest<'a> {
+    /// Reads the request line and the headers. The body is left on the stream.
+    /// ConnectionClosed is returned if the client closed the connection without sending
+    /// anything, for ex a health check probe
+    pub fn read_from(reader: &'a mut BufReader<Stream>, options: &ParseOptions) -> Result<Self> {
+        let deadline = options.request_read_timeout.map(|x| Instant::now() + x);
+        // NOTE: the line buffers come from a pool to not allocate for each line
+        let mut s = buffer_pool::take();
+        // NOTE: one more byte than allowed is enough to detect an oversized line
+        let limit = options.max_request_line_length as u64 + 1;
+        let read =
+            read_line_before(reader, &mut s, limit, deadline).map_err(
+                |e| match unwrap_io_error(e) {
+                    Error::FromUtf8Error(_) => Error::NonAsciiRequestLine,
+                    e => e,
+                },
+            )?;
+        if read == 0 {
+            Err(Error::ConnectionClosed)?;
+        }
+        if read > options.max_request_line_length {
+            Err(Error::RequestLineTooLong)?;
+        }
+        // NOTE: valid utf8 is not enough, non ascii characters must be percent-encoded
+        // https://datatracker.ietf.org/doc/html/rfc9112#section-3
+        if !s.is_ascii() {
+            Err(Error::NonAsciiRequestLine)?;
+        }
 
         if !s.ends_with("\r\n") {
             Err(Error::MissingCRLFFromLine)?;
@@ -32,63 +186,684 @@ impl TryFrom<&mut BufReader<TcpStream>> for HttpRequest {
         // example first line: GET /index.html HTTP/1.1
         let parts: Vec<_> = s.split(' ').collect();
         if parts.len() != 3 {
-            Err(Error::InvalidRequestLine(s.clone()))?;
+            Err(Error::InvalidRequestLine(s.to_string()))?;
         }
         let method = HttpMethod::from_str(parts[0])?;
-        let path = parts[1].to_string();
+        let (target, path_and_query) = RequestTarget::parse(parts[1])?;
+        if !target.allows(&method) {
+            Err(Error::InvalidRequestTarget(parts[1].to_string()))?;
+        }
+        let (path, query) = match path_and_query.split_once('?') {
+            Some((path, query)) => {
+                // NOTE: checked before parsing so that a huge query is never held in the map
+                if query.len() > options.max_query_length {
+                    Err(Error::QueryTooLong(query.len()))?;
+                }
+                let params = query.split('&').filter(|x| !x.is_empty()).count();
+                if params > options.max_query_params {
+                    Err(Error::TooManyQueryParams(params))?;
+                }
+                (path.to_string(), parse_query(query))
+            }
+            None => (path_and_query.to_string(), HashMap::new()),
+        };
+        let path = normalize_path(&path, options.merge_slashes, options.strip_trailing_slash);
+        // NOTE: an encoded slash still becomes a separator once decoded, for ex in files
+        let depth = percent_decode(&path).split('/').skip(1).count();
+        if depth > options.max_path_depth {
+            Err(Error::PathTooDeep(depth))?;
+        }
         let version = HttpVersion::from_str(parts[2])?;
 
         let mut headers = Vec::new();
-        let mut content_length: usize = 0;
+        // NOTE: u64 rather than usize so that a big length is rejected with 413 and not
+        // a parse error on 32-bit targets
+        let mut content_length: u64 = 0;
+        let mut expect_continue = false;
+        let mut chunked = false;
 
         loop {
-            let mut s = String::new();
-            reader.read_line(&mut s)?;
-            if s.len() <= 2 {
-                break;
+            s.clear();
+            // NOTE: reading one more byte than allowed is enough to detect an oversized line
+            // without buffering all of it
+            let limit = options.max_header_line_length as u64 + 1;
+            read_line_before(reader, &mut s, limit, deadline).map_err(unwrap_io_error)?;
+            if s.len() > options.max_header_line_length {
+                let key = s.split_once(':').map(|(key, _)| key.trim().to_string());
+                Err(Error::HeaderLineTooLong(key))?;
+            }
+            match s.as_str() {
+                "\r\n" => break,
+                // NOTE: closed in the middle of the headers, there is no request to answer
+                "" => Err(io::Error::from(io::ErrorKind::UnexpectedEof))?,
+                _ => {}
             }
 
-            let header = HttpHeader::try_from(s)?;
+            if headers.len() == options.max_headers {
+                Err(Error::TooManyHeaders(headers.len() + 1))?;
+            }
+            let header = HttpHeader::try_from(s.as_str())?;
 
             if header.key.to_lowercase() == "content-length" {
-                content_length = header.value.parse::<_>()?;
+                // NOTE: parse would also accept a sign, for ex +42
+                // https://datatracker.ietf.org/doc/html/rfc9110#section-8.6
+                let digits =
+                    !header.value.is_empty() && header.value.bytes().all(|x| x.is_ascii_digit());
+                if !digits {
+                    Err(Error::InvalidContentLength(header.value.clone()))?;
+                }
+                // NOTE: only digits are left, so parse can only fail on an overflow. Such a
+                // length is valid, just too large
+                content_length = header
+                    .value
+                    .parse()
+                    .map_err(|_| Error::PayloadTooLarge(u64::MAX))?;
+            }
+            // NOTE: chunked must be the last transfer coding
+            if header.key.to_lowercase() == "transfer-encoding"
+                && header
+                    .value
+                    .rsplit(',')
+                    .next()
+                    .is_some_and(|x| x.trim().eq_ignore_ascii_case("chunked"))
+            {
+                chunked = true;
+            }
+            if header.key.eq_ignore_ascii_case("te") {
+                validate_te(&header.value)?;
+            }
+            // NOTE: 100-continue is the only expectation defined, anything else must be
+            // rejected before the request is processed
+            // https://datatracker.ietf.org/doc/html/rfc9110#section-10.1.1
+            if header.key.to_lowercase() == "expect" {
+                match header.value.trim().eq_ignore_ascii_case("100-continue") {
+                    true => expect_continue = true,
+                    false => Err(Error::UnsupportedExpectation(header.value.clone()))?,
+                }
             }
 
             headers.push(header);
         }
 
-        let http_body = {
-            match content_length {
-                0 => None,
-                x => {
-                    let mut body = vec![0; x];
-                    reader.read_exact(&mut body)?;
-                    let body = String::from_utf8(body)?;
-
-                    Some(HttpBody::Text(body))
-                }
-            }
+        // NOTE: the check happens before sending 100 Continue so that a client waiting
+        // for it does not transfer a body we would reject anyway. The size of a chunked
+        // body is only known while reading it
+        if content_length > options.max_body_size as u64 {
+            Err(Error::PayloadTooLarge(content_length))?;
+        }
+        let peer_addr = reader.get_ref().peer_addr().ok();
+        // https://datatracker.ietf.org/doc/html/rfc9112#section-6.3
+        // Transfer-Encoding overrides Content-Length
+        let body = match chunked {
+            true => BodyReader::chunked(
+                reader,
+                expect_continue,
+                options.max_body_size,
+                deadline,
+                options.max_header_line_length,
+                options.max_headers,
+            ),
+            false => BodyReader::with_length(
+                reader,
+                content_length,
+                expect_continue,
+                options.max_body_size,
+                deadline,
+            ),
         };
 
         Ok(HttpRequest {
             method,
+            target,
             path,
+            query,
+            params: HashMap::new(),
             version,
             headers,
-            body: http_body,
+            peer_addr,
+            body,
+        })
+    }
+
+    /// True if the client accepts trailer fields in a chunked response (TE: trailers).
+    /// The trailers of HttpResponse::set_trailers are sent after any chunked streamed
+    /// body, a client that does not accept them may discard them. A handler whose
+    /// trailers are needed to use the response can check this first
+    pub fn accepts_trailers(&self) -> bool {
+        self.header("te")
+            .is_some_and(|x| te_codings(x).any(|coding| coding.eq_ignore_ascii_case("trailers")))
+    }
+
+    /// Reader over the remaining body bytes. It stops at the end of the body
+    pub fn body_reader(&mut self) -> &mut BodyReader<'a> {
+        &mut self.body
+    }
+
+    /// Reads the remaining body in memory, as raw bytes so that binary uploads are
+    /// accepted. The content codings of the request are removed, see content_codings
+    pub fn read_body(&mut self) -> Result<Option<HttpBody>> {
+        // NOTE: checked before reading so that a client waiting for 100 Continue does
+        // not send a body we can not decode
+        let codings = self.content_codings()?;
+        let mut body = Vec::new();
+        decode_body(&mut self.body, &codings).read_to_end(&mut body)?;
+        match body.is_empty() {
+            true => Ok(None),
+            false => Ok(Some(HttpBody::Bytes(body))),
+        }
+    }
+
+    /// Codings applied to the body, in the order they were applied. Content-Encoding
+    /// is a list and may be repeated, for ex deflate, gzip. An unknown coding is
+    /// UnsupportedMediaType
+    /// https://datatracker.ietf.org/doc/html/rfc9110#section-8.4
+    pub fn content_codings(&self) -> Result<Vec<ContentCoding>> {
+        let mut codings = Vec::new();
+        let values = self
+            .headers
+            .iter()
+            .filter(|header| header.key.eq_ignore_ascii_case("content-encoding"))
+            .flat_map(|header| header.value.split(','));
+        for coding in values.map(|x| x.trim().to_lowercase()) {
+            // NOTE: identity is no coding at all, and an empty element is allowed in a list
+            if coding.is_empty() || coding == "identity" {
+                continue;
+            }
+            match ContentCoding::from_str(&coding) {
+                Ok(x) => codings.push(x),
+                Err(_) => Err(Error::UnsupportedMediaType(coding))?,
+            }
+        }
+        Ok(codings)
+    }
+
+    /// Reads the remaining body as decoded utf8 text, empty if there is no body.
+    /// The text is owned as the body is read from the stream
+    pub fn body_text(&mut self) -> Result<String> {
+        let body = self
+            .read_body()?
+            .map(|x| x.decoded_bytes())
+            .transpose()?
+            .unwrap_or_default();
+        Ok(String::from_utf8(body)?)
+    }
+
+    /// Parsed Content-Type header, None if there is none or it is invalid
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.header("content-type").and_then(ContentType::parse)
+    }
+
+    /// Deserializes the body according to its Content-Type, either json or
+    /// application/x-www-form-urlencoded. Any other type is UnsupportedMediaType
+    pub fn extract<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let content_type = self
+            .content_type()
+            .map(|x| x.media_type().to_string())
+            .unwrap_or_default();
+        let body = self
+            .read_body()?
+            .map(|x| x.decoded_bytes())
+            .transpose()?
+            .unwrap_or_default();
+        match content_type.as_ref() {
+            "application/json" => Ok(serde_json::from_slice(&body)?),
+            "application/x-www-form-urlencoded" => Ok(serde_urlencoded::from_bytes(&body)?),
+            _ => Err(Error::UnsupportedMediaType(content_type)),
+        }
+    }
+
+    /// Discards what is left of the body so the connection is not closed with unread
+    /// data. Nothing is read if the client still waits for 100 Continue
+    pub fn discard_body(&mut self) -> Result<()> {
+        if !self.body.awaits_continue() {
+            std::io::copy(&mut self.body, &mut std::io::sink())?;
+        }
+        Ok(())
+    }
+}
+
+impl HttpRequest<'_> {
+    /// Value of the first header matching key, case insensitive
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.key.eq_ignore_ascii_case(key))
+            .map(|header| header.value.as_str())
+    }
+
+    /// Quality the client gives to a mime type in its Accept header, between 0 and 1.
+    /// The most specific matching range wins (type/subtype > type/* > */*).
+    /// Without Accept header, everything is accepted
+    /// https://datatracker.ietf.org/doc/html/rfc9110#section-12.5.1
+    pub fn accept_quality(&self, mime: &str) -> f32 {
+        let Some(accept) = self.header("accept") else {
+            return 1.0;
+        };
+        let (mime_type, mime_subtype) = mime.split_once('/').unwrap_or((mime, ""));
+
+        let mut best: Option<(u8, f32)> = None;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_range = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|x| x.trim().strip_prefix("q="))
+                .find_map(|x| x.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            let (range_type, range_subtype) =
+                media_range.split_once('/').unwrap_or((media_range, ""));
+            let specificity = match (range_type, range_subtype) {
+                ("*", "*") => 0,
+                (t, "*") if t.eq_ignore_ascii_case(mime_type) => 1,
+                (t, st)
+                    if t.eq_ignore_ascii_case(mime_type)
+                        && st.eq_ignore_ascii_case(mime_subtype) =>
+                {
+                    2
+                }
+                _ => continue,
+            };
+            let better = match best {
+                None => true,
+                Some((x, _)) => specificity > x,
+            };
+            if better {
+                best = Some((specificity, quality));
+            }
+        }
+        best.map_or(0.0, |(_, quality)| quality.clamp(0.0, 1.0))
+    }
+
+    /// Scheme and authority the client used to reach the server, for ex
+    /// http://example.com:8080, taken from the absolute target or the Host header.
+    /// Behind a proxy, X-Forwarded-Host and X-Forwarded-Proto take precedence: they are
+    /// set by the proxy and can not be trusted otherwise. None when the host is missing
+    /// or is not a valid authority, so that it can not inject anything in a header
+    pub fn base_url(&self, behind_proxy: bool) -> Option<String> {
+        // NOTE: each proxy appends its value, the first one is the client's
+        let forwarded = |key| match behind_proxy {
+            true => self
+                .header(key)
+                .and_then(|x| x.split(',').next())
+                .map(str::trim),
+            false => None,
+        };
+        let (scheme, authority) = match &self.target {
+            RequestTarget::Absolute { scheme, authority } => (scheme.as_str(), authority.as_str()),
+            _ => ("http", self.header("host")?),
+        };
+        let scheme = forwarded("x-forwarded-proto")
+            .unwrap_or(scheme)
+            .to_lowercase();
+        let authority = forwarded("x-forwarded-host").unwrap_or(authority);
+        let valid_authority = !authority.is_empty()
+            && authority
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || ".-:[]".contains(c));
+        match (scheme == "http" || scheme == "https") && valid_authority {
+            true => Some(format!("{}://{}", scheme, authority)),
+            false => None,
+        }
+    }
+
+    /// http/1.1 connections are persistent unless the client sends Connection: close,
+    /// http/1.0 ones only with Connection: keep-alive
+    /// https://datatracker.ietf.org/doc/html/rfc9112#section-9.3
+    pub fn wants_keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.header("connection").is_some_and(|x| {
+                x.split(',')
+                    .any(|value| value.trim().eq_ignore_ascii_case(option))
+            })
+        };
+        match self.version {
+            HttpVersion::V1_0 => has_option("keep-alive"),
+            HttpVersion::V1_1 => !has_option("close"),
+        }
+    }
+
+    /// True if the Accept header allows the mime type, q=0 meaning not acceptable
+    pub fn accepts(&self, mime: &str) -> bool {
+        self.accept_quality(mime) > 0.0
+    }
+}
+
+pub fn normalize_path(path: &str, merge_slashes: bool, strip_trailing_slash: bool) -> String {
+    let mut normalized = String::with_capacity(path.len());
+    for c in path.chars() {
+        if merge_slashes && c == '/' && normalized.ends_with('/') {
+            continue;
+        }
+        normalized.push(c);
+    }
+    if strip_trailing_slash {
+        while normalized.len() > 1 && normalized.ends_with('/') {
+            normalized.pop();
+        }
+    }
+    normalized
+}
+
+/// Rebuilds the raw request line and headers from their parsed parts. Query parameters
+/// are sorted by key as their original order is not kept. The body is not included as
+/// it is not read yet.
+impl From<&HttpRequest<'_>> for Vec<u8> {
+    fn from(request: &HttpRequest) -> Self {
+        let mut target = request.path.clone();
+        if !request.query.is_empty() {
+            let mut params: Vec<_> = request.query.iter().collect();
+            params.sort();
+            let params: Vec<_> = params
+                .into_iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            target.push('?');
+            target.push_str(&params.join("&"));
+        }
+
+        let mut res = Vec::new();
+        let val = format!(
+            "{} {} {}\r\n",
+            request.method.as_ref(),
+            target,
+            request.version.as_ref()
+        );
+        res.extend(val.as_bytes());
+
+        for header in request.headers.iter().cloned() {
+            res.extend::<Vec<u8>>(header.into());
+        }
+        res.extend(b"\r\n");
+        res
+    }
+}
+
+/// Transfer codings a client may list in TE, with trailers which is not a coding but
+/// the acceptance of trailer fields. The server only applies chunked, the others
+/// are accepted as the client does not require them
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-10.1.4
+const TE_CODINGS: [&str; 7] = [
+    "trailers",
+    "chunked",
+    "gzip",
+    "x-gzip",
+    "deflate",
+    "compress",
+    "x-compress",
+];
+
+/// Codings of a TE header without their parameters, for ex gzip;q=0.5 gives gzip
+fn te_codings(te: &str) -> impl Iterator<Item = &str> {
+    te.split(',')
+        .map(|x| x.split(';').next().unwrap_or_default().trim())
+        .filter(|x| !x.is_empty())
+}
+
+/// Rejects a TE header listing a coding the server does not know
+fn validate_te(te: &str) -> Result<()> {
+    for coding in te_codings(te) {
+        if !TE_CODINGS.iter().any(|x| x.eq_ignore_ascii_case(coding)) {
+            Err(Error::UnsupportedTransferCoding(coding.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Content codings are case insensitive, the x- aliases come from http/1.0
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-8.4.1
+fn canonical_coding(coding: &str) -> String {
+    let coding = coding.trim().to_lowercase();
+    match coding.as_ref() {
+        "x-gzip" => "gzip".to_string(),
+        "x-compress" => "compress".to_string(),
+        _ => coding,
+    }
+}
+
+/// Quality an Accept-Encoding header gives to the content coding, 0 if it is not listed.
+/// A coding with q=0 is not acceptable
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-12.5.3
+pub fn encoding_quality(accept_encoding: &str, coding: &str) -> f32 {
+    let coding = canonical_coding(coding);
+    accept_encoding
+        .split(',')
+        .find_map(|x| {
+            let mut params = x.split(';');
+            let accepted = canonical_coding(params.next().unwrap_or_default());
+            let quality = params
+                .filter_map(|x| x.trim().strip_prefix("q="))
+                .find_map(|x| x.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (accepted == coding).then_some(quality.clamp(0.0, 1.0))
         })
+        .unwrap_or(0.0)
+}
+
+/// Content codings the server can apply to a response or remove from a request
+#[derive(EnumString, AsRefStr, Debug, Clone, Copy, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum ContentCoding {
+    Gzip,
+    /// zlib format, as specified for http
+    Deflate,
+}
+
+/// Reader over the content of a body encoded with codings, in the order they were
+/// applied. The last coding applied is the first one removed
+pub fn decode_body<'r>(body: impl Read + 'r, codings: &[ContentCoding]) -> Box<dyn Read + 'r> {
+    let mut reader: Box<dyn Read + 'r> = Box::new(body);
+    for coding in codings.iter().rev() {
+        reader = match coding {
+            ContentCoding::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+            ContentCoding::Deflate => Box::new(flate2::read::ZlibDecoder::new(reader)),
+        };
+    }
+    reader
+}
+
+/// Coding with the highest quality in the Accept-Encoding header, equal qualities are
+/// decided by the order of priority. None if the client accepts none of them
+pub fn negotiate_encoding(
+    accept_encoding: &str,
+    priority: &[ContentCoding],
+) -> Option<ContentCoding> {
+    let mut best: Option<(ContentCoding, f32)> = None;
+    for coding in priority {
+        let quality = encoding_quality(accept_encoding, coding.as_ref());
+        let better = match best {
+            None => quality > 0.0,
+            Some((_, x)) => quality > x,
+        };
+        if better {
+            best = Some((*coding, quality));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// Errors raised while reading the stream are wrapped in io errors, see body.rs. They are
+/// unwrapped so that the client gets a response rather than a closed connection
+fn unwrap_io_error(e: io::Error) -> Error {
+    match e.get_ref().is_some_and(|x| x.is::<Error>()) {
+        true => *e
+            .into_inner()
+            .and_then(|x| x.downcast::<Error>().ok())
+            .expect("Error should have been checked"),
+        false => e.into(),
     }
 }
 
+/// Parses a query string such as a=1&b=hello%20world.
+/// If a key is repeated, the last value wins.
+pub fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (percent_decode(key), percent_decode(value)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// Decodes %XX sequences and + as a space. Invalid sequences are kept as is.
+pub fn percent_decode(s: &str) -> String {
+    decode_percent(s, true)
+}
+
+/// Same as percent_decode for a path segment, where + is not a space
+pub fn percent_decode_path(s: &str) -> String {
+    decode_percent(s, false)
+}
+
+fn decode_percent(s: &str, plus_as_space: bool) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hi = (bytes[i + 1] as char).to_digit(16);
+                let lo = (bytes[i + 2] as char).to_digit(16);
+                match (hi, lo) {
+                    (Some(hi), Some(lo)) => {
+                        decoded.push((hi * 16 + lo) as u8);
+                        i += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            x => decoded.push(x),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 #[derive(Debug)]
 pub struct HttpResponse {
     pub status: HttpStatus,
     pub version: HttpVersion,
     pub headers: Vec<HttpHeader>,
     pub body: Option<HttpBody>,
+    /// body of unknown length, sent after body if both are set, see stream_response
+    pub stream: Option<BodyStream>,
+}
+
+/// Source of a body whose length is not known in advance. It is compressed and framed
+/// while being written so that it is never fully in memory
+pub struct BodyStream {
+    source: Source,
+    chunked: bool,
+    /// coding and compression level applied while writing
+    encoding: Option<(ContentCoding, u32)>,
+    /// called once the body is written, see HttpResponse::set_trailers
+    trailers: Option<Trailers>,
+    /// known length, sent as Content-Length, see HttpResponse::sized_stream_response
+    length: Option<u64>,
+}
+
+/// Computes the trailer fields, for ex a checksum of the streamed body
+pub type Trailers = Box<dyn FnOnce() -> Vec<HttpHeader> + Send>;
+
+/// Writes a streamed body, see HttpResponse::writer_response
+pub type BodyWriter = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+enum Source {
+    Reader(Box<dyn Read + Send>),
+    Writer(BodyWriter),
+}
+
+impl Source {
+    fn copy_to(self, writer: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Self::Reader(mut reader) => io::copy(&mut reader, writer).map(|_| ()),
+            Self::Writer(body_writer) => body_writer(writer),
+        }
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BodyStream")
+            .field("chunked", &self.chunked)
+            .field("encoding", &self.encoding)
+            .field("trailers", &self.trailers.is_some())
+            .field("length", &self.length)
+            .finish()
+    }
 }
+
+impl BodyStream {
+    pub fn new<R: Read + Send + 'static>(reader: R) -> Self {
+        Self::from_source(Source::Reader(Box::new(reader)))
+    }
+
+    fn from_source(source: Source) -> Self {
+        Self {
+            source,
+            chunked: false,
+            encoding: None,
+            trailers: None,
+            length: None,
+        }
+    }
+
+    fn write_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        match self.chunked {
+            true => {
+                let mut chunked = ChunkedWriter::new(&mut *writer);
+                copy_encoded(self.source, &mut chunked, self.encoding)?;
+                let trailers = self.trailers.map(|x| x()).unwrap_or_default();
+                chunked.finish(&trailers)?;
+            }
+            false => copy_encoded(self.source, writer, self.encoding)?,
+        }
+        Ok(())
+    }
+}
+
+/// Copies the source to the writer, compressing it on the fly when a coding is given
+fn copy_encoded<W: Write>(
+    source: Source,
+    writer: &mut W,
+    encoding: Option<(ContentCoding, u32)>,
+) -> io::Result<()> {
+    match encoding {
+        None => source.copy_to(writer)?,
+        Some((ContentCoding::Gzip, level)) => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(writer, flate2::Compression::new(level));
+            source.copy_to(&mut encoder)?;
+            encoder.finish()?;
+        }
+        Some((ContentCoding::Deflate, level)) => {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(writer, flate2::Compression::new(level));
+            source.copy_to(&mut encoder)?;
+            encoder.finish()?;
+        }
+    }
+    Ok(())
+}
+
+impl From<HttpError> for HttpResponse {
+    fn from(error: HttpError) -> Self {
+        match error.message {
+            None => Self::empty_response(error.status),
+            Some(message) => {
+                let mut response = Self::content_response(&message, "text/plain");
+                response.status = error.status;
+                response
+            }
+        }
+    }
+}
+
 impl From<HttpResponse> for Vec<u8> {
     fn from(response: HttpResponse) -> Self {
+        response.debug_assert_content_length();
         let mut res = Vec::new();
         let val = format!(
             "{} {}\r\n",
@@ -108,99 +883,507 @@ impl From<HttpResponse> for Vec<u8> {
                 res.extend(body_bytes);
             }
         }
+        if let Some(stream) = response.stream {
+            // NOTE: the response is already built, a read error can only truncate it
+            let _ = stream.write_to(&mut res);
+        }
 
         res
     }
 }
 
 impl HttpResponse {
-    pub fn empty_response(status: HttpStatus) -> Self {
+    /// Writes the response incrementally instead of building it in memory first.
+    /// The writer is flushed at the end so it can be wrapped in a BufWriter
+    /// A streamed body is consumed.
+    pub fn write_to<W: Write>(&mut self, writer: &mut W) -> Result<()> {
+        self.debug_assert_content_length();
+        // NOTE: the encoders of a streamed body and flush would fail on a signal
+        let writer = &mut RetryInterrupted(writer);
+        if self.is_close_delimited() {
+            self.remove_header("Trailer");
+        }
+        write!(
+            writer,
+            "{} {}\r\n",
+            self.version.as_ref(),
+            self.status.as_ref()
+        )?;
+        for header in self.headers.iter() {
+            write!(writer, "{}: {}\r\n", header.key, header.value)?;
+        }
+        writer.write_all(b"\r\n")?;
+        if let Some(body) = &self.body {
+            writer.write_all(body.as_bytes())?;
+        }
+        if let Some(stream) = self.stream.take() {
+            stream.write_to(writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Replaces all the headers with the same name (case insensitive), keeping the
+    /// position of the first one. Headers should be set through this method or
+    /// append_header so that no conflicting header is sent
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        let position = self
+            .headers
+            .iter()
+            .position(|header| header.key.eq_ignore_ascii_case(key));
+        self.remove_header(key);
+        let header = HttpHeader {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+        match position {
+            Some(i) => self.headers.insert(i, header),
+            None => self.headers.push(header),
+        }
+    }
+
+    /// Adds a value to a header that accepts a comma separated list, for ex Vary.
+    /// The value is not added twice
+    pub fn append_header(&mut self, key: &str, value: &str) {
+        let existing = self
+            .headers
+            .iter_mut()
+            .find(|header| header.key.eq_ignore_ascii_case(key));
+        match existing {
+            None => self.headers.push(HttpHeader {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            Some(header) => {
+                if !header
+                    .value
+                    .split(',')
+                    .any(|x| x.trim().eq_ignore_ascii_case(value))
+                {
+                    header.value = format!("{}, {}", header.value, value);
+                }
+            }
+        }
+    }
+
+    /// Size of the headers once serialized, status line excluded
+    pub fn headers_size(&self) -> usize {
+        self.headers
+            .iter()
+            .map(|header| header.key.len() + header.value.len() + ": \r\n".len())
+            .sum()
+    }
+
+    pub fn remove_header(&mut self, key: &str) {
+        self.headers
+            .retain(|header| !header.key.eq_ignore_ascii_case(key));
+    }
+
+    /// Response without any header, prefer the other constructors
+    pub fn new(status: HttpStatus) -> Self {
         HttpResponse {
             status,
             version: HttpVersion::V1_1,
-            // https://datatracker.ietf.org/doc/html/rfc7230#section-3.3
-            // good practice to add a content length header
-            headers: vec![HttpHeader {
-                key: "Content-Length".to_string(),
-                value: "0".to_string(),
-            }],
+            headers: Vec::new(),
             body: None,
+            stream: None,
         }
     }
 
-    /// accepted_encodings is a list of comma separated values
-    pub fn content_response(
-        content: &str,
+    /// 200 response whose body is read from the reader while it is written. As its
+    /// length is unknown, there is no Content-Length: the end of the body is signaled
+    /// by closing the connection, which works with http/1.0 clients. See use_chunked
+    /// https://datatracker.ietf.org/doc/html/rfc9112#section-6.3
+    pub fn stream_response<R: Read + Send + 'static>(reader: R, content_type: &str) -> Self {
+        let mut response = Self::new(HttpStatus::Ok200);
+        response.set_header("Content-Type", content_type);
+        response.set_header("Connection", "close");
+        response.stream = Some(BodyStream::new(reader));
+        response
+    }
+
+    /// Same as stream_response for a body whose length is known, it is sent with a
+    /// Content-Length so the connection can be kept open. At most length bytes are read,
+    /// the reader must not provide less
+    pub fn sized_stream_response<R: Read + Send + 'static>(
+        reader: R,
+        length: u64,
         content_type: &str,
-        accepted_encodings: &str,
-    ) -> Result<Self> {
-        let mut compression = String::from("None");
-        for accepted_encoding in accepted_encodings.split(',') {
-            if accepted_encoding.trim() == "gzip" {
-                compression = String::from("gzip");
-                break;
-            }
+    ) -> Self {
+        let mut response = Self::new(HttpStatus::Ok200);
+        response.set_header("Content-Type", content_type);
+        response.set_header("Content-Length", &length.to_string());
+        let mut stream = BodyStream::new(reader.take(length));
+        stream.length = Some(length);
+        response.stream = Some(stream);
+        response
+    }
+
+    /// Same as stream_response but the body is written by body_writer, for ex a handler
+    /// that produces events over time, see sse. Flushing the writer sends what is
+    /// written so far to the client
+    pub fn writer_response<F>(body_writer: F, content_type: &str) -> Self
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    {
+        let mut response = Self::new(HttpStatus::Ok200);
+        response.set_header("Content-Type", content_type);
+        response.set_header("Connection", "close");
+        response.stream = Some(BodyStream::from_source(Source::Writer(Box::new(
+            body_writer,
+        ))));
+        response
+    }
+
+    /// Sends a streamed body with chunked framing instead of closing the connection.
+    /// Only http/1.1 clients support it
+    pub fn use_chunked(&mut self) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        if stream.length.is_some() {
+            return;
+        }
+        stream.chunked = true;
+        self.remove_header("Connection");
+        self.set_header("Transfer-Encoding", "chunked");
+    }
+
+    /// Catches a Content-Length computed by hand that does not match the body.
+    /// Responses without body are not checked: the Content-Length of a HEAD response
+    /// is the one of the GET response
+    fn debug_assert_content_length(&self) {
+        let (Some(body), None) = (&self.body, &self.stream) else {
+            return;
+        };
+        let content_length = self
+            .headers
+            .iter()
+            .find(|header| header.key.eq_ignore_ascii_case("content-length"))
+            .map(|header| header.value.parse::<usize>().ok());
+        if let Some(content_length) = content_length {
+            debug_assert_eq!(
+                content_length,
+                Some(body.content_length()),
+                "Content-Length does not match the body"
+            );
         }
+    }
 
-        let http_body = match compression.as_ref() {
-            "gzip" => HttpBody::gzip_from_content(content)?,
-            _ => HttpBody::from_content(content),
+    /// Sends trailer fields after a streamed body, computed once it is written. The
+    /// names are announced in the Trailer header. Trailers are only sent with chunked
+    /// framing, they are dropped for http/1.0 clients
+    /// https://datatracker.ietf.org/doc/html/rfc9110#section-6.5
+    pub fn set_trailers<F: FnOnce() -> Vec<HttpHeader> + Send + 'static>(
+        &mut self,
+        names: &[&str],
+        trailers: F,
+    ) {
+        let Some(stream) = &mut self.stream else {
+            return;
         };
+        stream.trailers = Some(Box::new(trailers));
+        self.set_header("Trailer", &names.join(", "));
+    }
 
-        let mut headers = vec![
-            HttpHeader {
-                key: "Content-Type".to_string(),
-                value: content_type.to_string(),
-            },
-            HttpHeader {
-                key: "Content-Length".to_string(),
-                value: http_body.content_length().to_string(),
-            },
-        ];
+    /// True if the connection must be closed for the client to know the body is over
+    pub fn is_close_delimited(&self) -> bool {
+        self.stream
+            .as_ref()
+            .is_some_and(|x| !x.chunked && x.length.is_none())
+    }
+
+    pub fn empty_response(status: HttpStatus) -> Self {
+        // https://datatracker.ietf.org/doc/html/rfc7230#section-3.3
+        // good practice to add a content length header, except for 1xx and 204
+        // responses which must not have one. A 304 would announce the length of the
+        // representation that is not sent
+        // https://datatracker.ietf.org/doc/html/rfc9110#section-8.6
+        let no_content = status.code() < 200
+            || status == HttpStatus::NoContent204
+            || status == HttpStatus::NotModified304;
+        let mut response = Self::new(status);
+        if !no_content {
+            response.set_header("Content-Length", "0");
+        }
+        response
+    }
+
+    pub fn redirect_response(location: &str) -> Self {
+        let mut response = Self::empty_response(HttpStatus::MovedPermanently301);
+        response.set_header("Location", location);
+        response
+    }
+
+    pub fn not_found() -> Self {
+        Self::empty_response(HttpStatus::NotFound404)
+    }
+
+    /// 400 with a plain text message telling the client what is wrong with its request
+    pub fn bad_request(message: &str) -> Self {
+        let mut response = Self::content_response(message, "text/plain");
+        response.status = HttpStatus::BadRequest400;
+        response
+    }
+
+    /// Empty 500, the details of the error are for the log, not the client
+    pub fn internal_error() -> Self {
+        Self::empty_response(HttpStatus::InternalServerError500)
+    }
 
-        match compression.as_ref() {
-            "gzip" => {
-                headers.push(HttpHeader {
-                    key: "Content-Encoding".to_string(),
-                    value: "gzip".to_string(),
-                });
+    /// 201 without body, the Location of the created resource is up to the caller
+    pub fn created() -> Self {
+        Self::empty_response(HttpStatus::Created201)
+    }
+
+    pub fn no_content() -> Self {
+        Self::empty_response(HttpStatus::NoContent204)
+    }
+
+    /// Uncompressed 200 response, see add_compression
+    pub fn content_response(content: &str, content_type: &str) -> Self {
+        Self::body_response(HttpBody::from_content(content), content_type)
+    }
+
+    /// Same as content_response without copying the content
+    pub fn static_response(content: &'static str, content_type: &str) -> Self {
+        Self::body_response(HttpBody::from_static(content), content_type)
+    }
+
+    /// Same as content_response for content that may not be valid utf8
+    pub fn bytes_response(content: Vec<u8>, content_type: &str) -> Self {
+        Self::body_response(HttpBody::from_bytes(content), content_type)
+    }
+
+    fn body_response(http_body: HttpBody, content_type: &str) -> Self {
+        let mut response = Self::new(HttpStatus::Ok200);
+        response.set_header("Content-Type", content_type);
+        response.set_header("Content-Length", &http_body.content_length().to_string());
+        response.body = Some(http_body);
+        response
+    }
+
+    /// Compresses a text or streamed body with the coding negotiated from the
+    /// Accept-Encoding header and the server priority. A streamed body is compressed
+    /// while it is written. Only the content types matching compressible are compressed,
+    /// see mime::is_compressible: compressing an image or an archive again wastes cpu and
+    /// can make it bigger.
+    /// accepted_encodings is the Accept-Encoding header, a list of comma separated values.
+    /// When None, the response is not compressed.
+    /// Vary: Accept-Encoding is sent with every response that could have been compressed,
+    /// even when it is not, so that a cache does not serve an uncompressed response to a
+    /// client that accepts gzip, or the opposite. Responses that are never compressed
+    /// do not vary and do not get it
+    pub fn add_compression(
+        &mut self,
+        accepted_encodings: Option<&str>,
+        priority: &[ContentCoding],
+        level: u32,
+        compressible: &[String],
+    ) -> Result<()> {
+        let coding = accepted_encodings.and_then(|x| negotiate_encoding(x, priority));
+        let compressible = self
+            .headers
+            .iter()
+            .find(|header| header.key.eq_ignore_ascii_case("content-type"))
+            .is_some_and(|header| crate::mime::is_compressible(&header.value, compressible));
+        if !compressible {
+            return Ok(());
+        }
+        // NOTE: the range was computed on the identity representation. The full
+        // representation of the same resource would still vary
+        if self.status == HttpStatus::PartialContent206 {
+            self.append_header("Vary", "Accept-Encoding");
+            return Ok(());
+        }
+
+        if let Some(stream) = &mut self.stream {
+            if let Some(coding) = coding {
+                stream.encoding = Some((coding, level));
+                // NOTE: the compressed length is only known once written
+                stream.length = None;
+                self.remove_header("Content-Length");
+                self.set_header("Content-Encoding", coding.as_ref());
+                self.weaken_etag();
             }
-            _ => {}
+            self.append_header("Vary", "Accept-Encoding");
+            return Ok(());
+        }
+
+        let Some(HttpBody::Text(content)) = &self.body else {
+            return Ok(());
+        };
+        // NOTE: a random or small body can grow, it is then sent as is since a coding
+        // the client accepts is never required. The size of a streamed body is not known
+        // in advance so it is always compressed
+        let http_body = match coding {
+            Some(coding) => Some(HttpBody::encode_content(content, coding, level)?)
+                .filter(|x| x.content_length() < content.len()),
+            None => None,
+        };
+        // NOTE: caches must not serve a compressed body to a client that does not accept it
+        self.append_header("Vary", "Accept-Encoding");
+        let (Some(http_body), Some(coding)) = (http_body, coding) else {
+            return Ok(());
         };
 
-        Ok(HttpResponse {
-            status: HttpStatus::Ok200,
-            version: HttpVersion::V1_1,
-            headers,
-            body: Some(http_body),
-        })
+        // NOTE: the length is the one of the encoded bytes that are written, a wrong
+        // value would make the next request of a keep-alive connection start at the
+        // wrong offset. See debug_assert_content_length
+        self.set_header("Content-Length", &http_body.content_length().to_string());
+        self.set_header("Content-Encoding", coding.as_ref());
+        self.body = Some(http_body);
+        self.weaken_etag();
+        Ok(())
+    }
+
+    /// A strong tag is computed on the identity representation, the compressed one is
+    /// only equivalent to it. The tag stays the same so that If-None-Match still matches
+    fn weaken_etag(&mut self) {
+        let etag = self
+            .headers
+            .iter()
+            .find(|header| header.key.eq_ignore_ascii_case("etag"))
+            .and_then(|header| EntityTag::parse(&header.value));
+        if let Some(etag) = etag {
+            self.set_header("ETag", &etag.into_weak().to_string());
+        }
     }
 }
 
-#[derive(EnumString, AsRefStr, Debug)]
+#[derive(EnumString, AsRefStr, Debug, Clone, PartialEq, Eq)]
 pub enum HttpMethod {
     #[strum(serialize = "GET", ascii_case_insensitive)]
     Get,
+    #[strum(serialize = "HEAD", ascii_case_insensitive)]
+    Head,
     #[strum(serialize = "POST", ascii_case_insensitive)]
     Post,
+    #[strum(serialize = "PUT", ascii_case_insensitive)]
+    Put,
+    #[strum(serialize = "DELETE", ascii_case_insensitive)]
+    Delete,
+    #[strum(serialize = "OPTIONS", ascii_case_insensitive)]
+    Options,
+    #[strum(serialize = "CONNECT", ascii_case_insensitive)]
+    Connect,
 }
 
-#[derive(EnumString, AsRefStr, Debug)]
+#[derive(EnumString, AsRefStr, Debug, PartialEq, Eq)]
 pub enum HttpVersion {
+    #[strum(serialize = "HTTP/1.0", ascii_case_insensitive)]
+    V1_0,
     #[strum(serialize = "HTTP/1.1", ascii_case_insensitive)]
     V1_1,
 }
 
-#[derive(AsRefStr, Debug)]
+#[derive(AsRefStr, EnumIter, Debug, Clone, Copy, PartialEq)]
 pub enum HttpStatus {
+    #[strum(serialize = "101 Switching Protocols")]
+    SwitchingProtocols101,
     #[strum(serialize = "200 OK")]
     Ok200,
+    #[strum(serialize = "200 Connection Established")]
+    ConnectionEstablished200,
+    #[strum(serialize = "403 Forbidden")]
+    Forbidden403,
     #[strum(serialize = "404 Not Found")]
     NotFound404,
     #[strum(serialize = "201 Created")]
     Created201,
+    #[strum(serialize = "202 Accepted")]
+    Accepted202,
+    #[strum(serialize = "204 No Content")]
+    NoContent204,
+    #[strum(serialize = "206 Partial Content")]
+    PartialContent206,
+    #[strum(serialize = "301 Moved Permanently")]
+    MovedPermanently301,
+    #[strum(serialize = "302 Found")]
+    Found302,
+    #[strum(serialize = "304 Not Modified")]
+    NotModified304,
+    #[strum(serialize = "400 Bad Request")]
+    BadRequest400,
+    #[strum(serialize = "405 Method Not Allowed")]
+    MethodNotAllowed405,
+    #[strum(serialize = "408 Request Timeout")]
+    RequestTimeout408,
+    #[strum(serialize = "412 Precondition Failed")]
+    PreconditionFailed412,
+    #[strum(serialize = "413 Payload Too Large")]
+    PayloadTooLarge413,
+    #[strum(serialize = "414 URI Too Long")]
+    UriTooLong414,
+    #[strum(serialize = "415 Unsupported Media Type")]
+    UnsupportedMediaType415,
+    #[strum(serialize = "416 Range Not Satisfiable")]
+    RangeNotSatisfiable416,
+    #[strum(serialize = "417 Expectation Failed")]
+    ExpectationFailed417,
+    #[strum(serialize = "426 Upgrade Required")]
+    UpgradeRequired426,
+    #[strum(serialize = "429 Too Many Requests")]
+    TooManyRequests429,
+    #[strum(serialize = "431 Request Header Fields Too Large")]
+    RequestHeaderFieldsTooLarge431,
     #[strum(serialize = "500 Internal Server Error")]
     InternalServerError500,
+    #[strum(serialize = "502 Bad Gateway")]
+    BadGateway502,
+    #[strum(serialize = "503 Service Unavailable")]
+    ServiceUnavailable503,
+}
+
+impl HttpStatus {
+    pub fn code(&self) -> u16 {
+        self.as_ref()[..3]
+            .parse()
+            .expect("Status should start with its code")
+    }
+
+    /// None if the code has no variant
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::iter().find(|x| x.code() == code)
+    }
+}
+
+impl From<&Error> for HttpStatus {
+    fn from(error: &Error) -> Self {
+        match error {
+            // NOTE: errors raised while reading the body are wrapped in io errors
+            Error::IoError(e) => match e.get_ref().and_then(|x| x.downcast_ref::<Error>()) {
+                Some(error) => Self::from(error),
+                None => Self::InternalServerError500,
+            },
+            Error::Http(e) => e.status,
+            Error::RequestReadTimeout => Self::RequestTimeout408,
+            Error::PayloadTooLarge(_) => Self::PayloadTooLarge413,
+            Error::HeaderLineTooLong(_) | Error::TooManyHeaders(_) => {
+                Self::RequestHeaderFieldsTooLarge431
+            }
+            Error::RequestLineTooLong | Error::PathTooDeep(_) | Error::QueryTooLong(_) => {
+                Self::UriTooLong414
+            }
+            Error::UnsupportedMediaType(_) => Self::UnsupportedMediaType415,
+            Error::UnsupportedExpectation(_) => Self::ExpectationFailed417,
+            Error::InvalidRequestLine(_)
+            | Error::NonAsciiRequestLine
+            | Error::InvalidRequestTarget(_)
+            | Error::InvalidContentLength(_)
+            | Error::InvalidChunk
+            | Error::MissingCRLFFromLine
+            | Error::InvalidHeader
+            | Error::UnsupportedTransferCoding(_)
+            | Error::TooManyQueryParams(_)
+            | Error::StrumParseError(_)
+            | Error::ParseIntError(_)
+            | Error::FromUtf8Error(_)
+            | Error::JsonError(_)
+            | Error::FormError(_) => Self::BadRequest400,
+            _ => Self::InternalServerError500,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -209,19 +1392,71 @@ pub struct HttpHeader {
     pub value: String,
 }
 
-impl TryFrom<String> for HttpHeader {
+/// Header names whose usual spelling is not Train-Case
+const HEADER_NAME_EXCEPTIONS: [&str; 9] = [
+    "ETag",
+    "WWW-Authenticate",
+    "TE",
+    "DNT",
+    "X-XSS-Protection",
+    "Content-MD5",
+    "Sec-WebSocket-Accept",
+    "Sec-WebSocket-Protocol",
+    "Sec-WebSocket-Version",
+];
+
+/// Header name in Train-Case, for ex content-type becomes Content-Type, except for the
+/// names usually spelled otherwise such as ETag. Header names are case insensitive but
+/// some clients only recognize this spelling
+pub fn canonicalize_header_name(name: &str) -> String {
+    if let Some(x) = HEADER_NAME_EXCEPTIONS
+        .iter()
+        .find(|x| x.eq_ignore_ascii_case(name))
+    {
+        return x.to_string();
+    }
+    name.split('-')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => {
+                    first.to_ascii_uppercase().to_string() + &chars.as_str().to_ascii_lowercase()
+                }
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// True for a non empty token, for ex a header name or a method
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-5.6.2
+pub fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+impl TryFrom<&str> for HttpHeader {
     type Error = Error;
 
-    fn try_from(line: String) -> Result<Self> {
+    /// The name must be a token directly followed by the colon: whitespace before the
+    /// colon or at the start of the line (obsolete line folding) is rejected, as is a
+    /// CR or NUL in the value
+    /// https://datatracker.ietf.org/doc/html/rfc9112#section-5
+    fn try_from(line: &str) -> Result<Self> {
         if !line.ends_with("\r\n") {
             Err(Error::MissingCRLFFromLine)?;
         }
 
         let (key, value) = line[..line.len() - 2]
             .split_once(':')
-            .ok_or_else(|| Error::InvalidHeader)?;
+            .ok_or(Error::InvalidHeader)?;
+        if !is_token(key) || value.contains(['\r', '\0']) {
+            Err(Error::InvalidHeader)?;
+        }
         Ok(Self {
-            key: key.trim().to_string(),
+            key: key.to_string(),
             value: value.trim().to_string(),
         })
     }
@@ -235,35 +1470,428 @@ impl From<HttpHeader> for Vec<u8> {
 
 #[derive(Debug, Clone)]
 pub enum HttpBody {
-    Text(String),
+    /// borrowed for static content, for ex a health check, to avoid a copy
+    Text(Cow<'static, str>),
     Gzip(Vec<u8>),
+    /// raw content, not necessarily valid utf8, for ex an image
+    Bytes(Vec<u8>),
 }
 
 impl From<HttpBody> for Vec<u8> {
     fn from(body: HttpBody) -> Self {
         match body {
-            HttpBody::Text(x) => Vec::from(x.as_bytes()),
-            HttpBody::Gzip(x) => x,
+            HttpBody::Text(x) => x.into_owned().into_bytes(),
+            HttpBody::Gzip(x) | HttpBody::Bytes(x) => x,
         }
     }
 }
 
 impl HttpBody {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(x) => x.as_bytes(),
+            Self::Gzip(x) | Self::Bytes(x) => x,
+        }
+    }
+
     pub fn content_length(&self) -> usize {
         match self {
-            Self::Text(x) => x.as_bytes().len(),
-            Self::Gzip(x) => x.len(),
+            Self::Text(x) => x.len(),
+            Self::Gzip(x) | Self::Bytes(x) => x.len(),
         }
     }
     pub fn from_content(content: &str) -> Self {
-        Self::Text(content.to_string())
+        Self::Text(Cow::Owned(content.to_string()))
+    }
+
+    pub fn from_static(content: &'static str) -> Self {
+        Self::Text(Cow::Borrowed(content))
+    }
+
+    /// Text if the bytes are valid utf8 so that it can be compressed, binary otherwise
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(x) => Self::Text(Cow::Owned(x)),
+            Err(e) => Self::Bytes(e.into_bytes()),
+        }
+    }
+
+    /// Raw content of the body, decompressed if needed
+    pub fn decoded_bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::Text(x) => Ok(x.as_bytes().to_vec()),
+            Self::Bytes(x) => Ok(x.clone()),
+            Self::Gzip(x) => {
+                let mut decoded = Vec::new();
+                flate2::read::GzDecoder::new(x.as_slice()).read_to_end(&mut decoded)?;
+                Ok(decoded)
+            }
+        }
+    }
+
+    /// Compressed content, a deflate body is kept as raw bytes as requests bodies are
+    /// only decoded from gzip
+    pub fn encode_content(content: &str, coding: ContentCoding, level: u32) -> Result<Self> {
+        match coding {
+            ContentCoding::Gzip => Self::gzip_from_content(content, level),
+            ContentCoding::Deflate => {
+                let mut e =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+                e.write_all(content.as_bytes())?;
+                Ok(Self::Bytes(e.finish()?))
+            }
+        }
     }
 
-    pub fn gzip_from_content(content: &str) -> Result<Self> {
+    /// level goes from 0 (no compression) to 9 (best compression)
+    pub fn gzip_from_content(content: &str, level: u32) -> Result<Self> {
         let body_bytes = content.as_bytes();
-        let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level));
         e.write_all(body_bytes).unwrap();
         let encoded_bytes = e.finish()?;
         Ok(Self::Gzip(encoded_bytes))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Two identical responses, as HttpResponse is not Clone
+    fn responses() -> Vec<(HttpResponse, HttpResponse)> {
+        let build = [
+            || HttpResponse::content_response("hello", "text/plain"),
+            || HttpResponse::static_response("hello", "text/plain"),
+            || HttpResponse::bytes_response(vec![0, 159, 146, 150], "application/octet-stream"),
+            || HttpResponse::empty_response(HttpStatus::NoContent204),
+            HttpResponse::not_found,
+        ];
+        build.iter().map(|f| (f(), f())).collect()
+    }
+
+    #[test]
+    fn write_to_matches_into_bytes() {
+        for (mut response, expected) in responses() {
+            let mut written = Vec::new();
+            response.write_to(&mut written).unwrap();
+            assert_eq!(written, Vec::<u8>::from(expected));
+        }
+    }
+
+    #[test]
+    fn write_to_matches_into_bytes_through_a_small_buffer() {
+        for (mut response, expected) in responses() {
+            let mut writer = io::BufWriter::with_capacity(1, Vec::new());
+            response.write_to(&mut writer).unwrap();
+            assert_eq!(writer.into_inner().unwrap(), Vec::<u8>::from(expected));
+        }
+    }
+
+    #[test]
+    fn stacked_codings_are_decoded_in_reverse_order() {
+        let encode = |bytes: Vec<u8>, coding: ContentCoding| match coding {
+            ContentCoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&bytes).unwrap();
+                encoder.finish().unwrap()
+            }
+            ContentCoding::Deflate => {
+                let mut encoder =
+                    flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&bytes).unwrap();
+                encoder.finish().unwrap()
+            }
+        };
+        let table = [
+            vec![],
+            vec![ContentCoding::Gzip],
+            vec![ContentCoding::Gzip, ContentCoding::Gzip],
+            vec![ContentCoding::Deflate, ContentCoding::Gzip],
+        ];
+        for codings in table {
+            let encoded = codings
+                .iter()
+                .fold(b"stacked".to_vec(), |bytes, coding| encode(bytes, *coding));
+            let mut decoded = Vec::new();
+            decode_body(encoded.as_slice(), &codings)
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, b"stacked", "{:?}", codings);
+        }
+    }
+
+    #[test]
+    fn header_names_are_canonicalized_to_train_case() {
+        let table = [
+            ("content-type", "Content-Type"),
+            ("CONTENT-LENGTH", "Content-Length"),
+            ("x-request-id", "X-Request-Id"),
+            ("etag", "ETag"),
+            ("www-authenticate", "WWW-Authenticate"),
+            ("te", "TE"),
+            ("host", "Host"),
+        ];
+        for (name, expected) in table {
+            assert_eq!(canonicalize_header_name(name), expected);
+        }
+    }
+
+    #[test]
+    fn headers_size_counts_the_serialized_header_lines() {
+        let mut response = HttpResponse::new(HttpStatus::Ok200);
+        assert_eq!(response.headers_size(), 0);
+        response.set_header("X-A", "1");
+        response.set_header("Content-Length", "0");
+        assert_eq!(
+            response.headers_size(),
+            "X-A: 1\r\nContent-Length: 0\r\n".len()
+        );
+    }
+
+    #[test]
+    fn constructors_set_the_status_line_and_headers() {
+        let table: [(HttpResponse, &str); 5] = [
+            (
+                HttpResponse::not_found(),
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+            ),
+            (
+                HttpResponse::bad_request("Missing body"),
+                "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nContent-Length: 12\r\n\r\nMissing body",
+            ),
+            (
+                HttpResponse::internal_error(),
+                "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n",
+            ),
+            (
+                HttpResponse::created(),
+                "HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n",
+            ),
+            // NOTE: a 204 must not have a Content-Length
+            (HttpResponse::no_content(), "HTTP/1.1 204 No Content\r\n\r\n"),
+        ];
+        for (response, expected) in table {
+            assert_eq!(String::from_utf8(Vec::from(response)).unwrap(), expected);
+        }
+    }
+
+    /// Response announcing one byte more than its body
+    fn mismatched_response() -> HttpResponse {
+        let mut response = HttpResponse::content_response("hello", "text/plain");
+        response.set_header("Content-Length", "6");
+        response
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "Content-Length does not match the body")]
+    fn mismatched_content_length_panics_in_into_bytes() {
+        let _ = Vec::<u8>::from(mismatched_response());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "Content-Length does not match the body")]
+    fn mismatched_content_length_panics_in_write_to() {
+        mismatched_response().write_to(&mut Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn static_response_serializes_like_the_owned_one() {
+        let owned = HttpResponse::content_response("ok", "text/plain");
+        let borrowed = HttpResponse::static_response("ok", "text/plain");
+        assert!(matches!(
+            borrowed.body,
+            Some(HttpBody::Text(Cow::Borrowed("ok")))
+        ));
+        assert_eq!(Vec::<u8>::from(borrowed), Vec::<u8>::from(owned));
+    }
+
+    /// Values of the headers named key, whatever their case
+    fn header_values<'a>(response: &'a HttpResponse, key: &str) -> Vec<&'a str> {
+        response
+            .headers
+            .iter()
+            .filter(|header| header.key.eq_ignore_ascii_case(key))
+            .map(|header| header.value.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn set_header_replaces_the_header_whatever_its_case() {
+        let mut response = HttpResponse::content_response("hello", "text/plain");
+        response.set_header("Content-Length", "5");
+        response.set_header("content-length", "5");
+        assert_eq!(header_values(&response, "Content-Length"), vec!["5"]);
+
+        // NOTE: the header keeps its position
+        let keys: Vec<_> = response.headers.iter().map(|x| x.key.as_str()).collect();
+        assert_eq!(keys, vec!["Content-Type", "content-length"]);
+    }
+
+    #[test]
+    fn append_header_extends_the_list_once() {
+        let mut response = HttpResponse::content_response("hello", "text/plain");
+        response.append_header("Vary", "Accept");
+        response.append_header("vary", "Accept-Encoding");
+        response.append_header("Vary", "accept");
+        assert_eq!(
+            header_values(&response, "Vary"),
+            vec!["Accept, Accept-Encoding"]
+        );
+    }
+
+    #[test]
+    fn request_target_forms() {
+        assert_eq!(
+            RequestTarget::parse("/echo/hi?a=1").unwrap(),
+            (RequestTarget::Origin, "/echo/hi?a=1")
+        );
+        assert_eq!(
+            RequestTarget::parse("HTTP://example.com:8080/echo/hi").unwrap(),
+            (
+                RequestTarget::Absolute {
+                    scheme: "http".to_string(),
+                    authority: "example.com:8080".to_string()
+                },
+                "/echo/hi"
+            )
+        );
+        // NOTE: an empty path is equivalent to /
+        assert_eq!(RequestTarget::parse("https://example.com").unwrap().1, "/");
+        assert_eq!(
+            RequestTarget::parse("example.com:443").unwrap(),
+            (
+                RequestTarget::Authority("example.com:443".to_string()),
+                "example.com:443"
+            )
+        );
+        assert_eq!(
+            RequestTarget::parse("*").unwrap(),
+            (RequestTarget::Asterisk, "*")
+        );
+    }
+
+    #[test]
+    fn malformed_header_lines_are_rejected() {
+        let table = [
+            "\n",
+            "\r\n",
+            ":value\r\n",
+            "Host localhost\r\n",
+            "Host : localhost\r\n",
+            " Host: localhost\r\n",
+            "Host: local\0host\r\n",
+            "Host: localhost\n",
+        ];
+        for line in table {
+            assert!(HttpHeader::try_from(line).is_err(), "{:?}", line);
+        }
+        let header = HttpHeader::try_from("Host:  localhost \r\n").unwrap();
+        assert_eq!(header.key, "Host");
+        assert_eq!(header.value, "localhost");
+    }
+
+    #[test]
+    fn invalid_request_targets() {
+        for target in [
+            "",
+            "echo/hi",
+            "ftp://example.com/",
+            "http:///path",
+            "example.com:",
+            "example.com:port",
+        ] {
+            assert!(RequestTarget::parse(target).is_err(), "{:?}", target);
+        }
+    }
+
+    #[test]
+    fn request_target_forms_are_restricted_to_their_method() {
+        let authority = RequestTarget::Authority("example.com:443".to_string());
+        assert!(authority.allows(&HttpMethod::Connect));
+        assert!(!authority.allows(&HttpMethod::Get));
+        assert!(!RequestTarget::Origin.allows(&HttpMethod::Connect));
+        assert!(RequestTarget::Asterisk.allows(&HttpMethod::Options));
+        assert!(!RequestTarget::Asterisk.allows(&HttpMethod::Get));
+    }
+
+    #[test]
+    fn accept_encoding_ignores_case_whitespace_and_aliases() {
+        let gzip = [ContentCoding::Gzip];
+        for accept_encoding in [
+            "gzip",
+            "GZIP",
+            "  GZip  ",
+            "x-gzip",
+            "X-GZIP;q=0.8",
+            "invalid-encoding,\tgzip ; q=0.5",
+        ] {
+            assert_eq!(
+                negotiate_encoding(accept_encoding, &gzip),
+                Some(ContentCoding::Gzip),
+                "{:?}",
+                accept_encoding
+            );
+        }
+        for accept_encoding in ["", "gzipx", "gzip;q=0", "deflate"] {
+            assert_eq!(
+                negotiate_encoding(accept_encoding, &gzip),
+                None,
+                "{:?}",
+                accept_encoding
+            );
+        }
+        assert_eq!(encoding_quality("x-compress;q=0.3", "compress"), 0.3);
+    }
+
+    #[test]
+    fn equal_qualities_are_decided_by_the_priority() {
+        use ContentCoding::{Deflate, Gzip};
+
+        let accept_encoding = "gzip, deflate";
+        assert_eq!(
+            negotiate_encoding(accept_encoding, &[Gzip, Deflate]),
+            Some(Gzip)
+        );
+        assert_eq!(
+            negotiate_encoding(accept_encoding, &[Deflate, Gzip]),
+            Some(Deflate)
+        );
+        // NOTE: the quality comes first
+        assert_eq!(
+            negotiate_encoding("gzip;q=0.5, deflate", &[Gzip, Deflate]),
+            Some(Deflate)
+        );
+        assert_eq!(
+            negotiate_encoding(accept_encoding, &[Deflate]),
+            Some(Deflate)
+        );
+    }
+
+    #[test]
+    fn te_accepts_trailers_and_known_codings_only() {
+        for te in ["trailers", "Trailers, gzip;q=0.5", "deflate, chunked", ""] {
+            assert!(validate_te(te).is_ok(), "{}", te);
+        }
+        for te in ["bogus", "trailers, br;q=1"] {
+            assert!(validate_te(te).is_err(), "{}", te);
+        }
+    }
+
+    #[test]
+    fn normalize_path_merges_slashes() {
+        assert_eq!(normalize_path("//echo///hi", true, false), "/echo/hi");
+        assert_eq!(normalize_path("//echo///hi", false, false), "//echo///hi");
+    }
+
+    #[test]
+    fn normalize_path_strips_trailing_slashes() {
+        assert_eq!(normalize_path("/echo/hi//", false, true), "/echo/hi");
+        assert_eq!(normalize_path("/echo/hi/", false, false), "/echo/hi/");
+        // NOTE: the root path is kept
+        assert_eq!(normalize_path("//", true, true), "/");
+    }
+}
@@ -0,0 +1,26 @@
+pub mod acl;
+mod body;
+pub mod buffer_pool;
+mod cgi;
+pub mod config;
+mod date;
+pub mod directory;
+pub mod drain;
+pub mod error;
+mod etag;
+pub mod handlers;
+pub mod http;
+pub mod listener;
+pub mod mime;
+mod range;
+mod reaper;
+pub mod router;
+pub mod server;
+pub mod sse;
+mod threadpool;
+mod tunnel;
+pub mod upgrade;
+pub mod uploads;
+
+pub use crate::error::{Error, HttpError, Result};
+pub use crate::server::{Server, ServerBuilder};
@@ -0,0 +1,267 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::http::is_token;
+
+/// Content type associated to the file extension, case insensitive
+pub fn from_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let content_type = match extension.as_ref() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "application/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+/// Content types compressed by default, see is_compressible
+pub const COMPRESSIBLE_TYPES: [&str; 6] = [
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "image/svg+xml",
+    "application/wasm",
+];
+
+/// True if the content type matches one of the patterns, either a full type or type/*
+/// to match all its subtypes. The parameters and the case are ignored
+pub fn is_compressible(content_type: &str, patterns: &[String]) -> bool {
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix("/*") {
+            Some(type_) => content_type
+                .split_once('/')
+                .is_some_and(|(x, _)| x.eq_ignore_ascii_case(type_)),
+            None => content_type.eq_ignore_ascii_case(pattern),
+        })
+}
+
+/// Media type and parameters of a Content-Type header, for ex
+/// application/json; charset=utf-8. The media type and the parameter names are case
+/// insensitive and lowercased, the values are kept as is
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-8.3.1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    media_type: String,
+    params: HashMap<String, String>,
+}
+
+impl ContentType {
+    /// None if the media type is not type/subtype. Invalid parameters are ignored
+    pub fn parse(s: &str) -> Option<Self> {
+        let (media_type, mut rest) = s.split_once(';').unwrap_or((s, ""));
+        let media_type = media_type.trim().to_lowercase();
+        match media_type.split_once('/') {
+            Some((type_, subtype)) if is_token(type_) && is_token(subtype) => {}
+            _ => return None,
+        }
+
+        let mut params = HashMap::new();
+        while let Some((name, after)) = rest.split_once('=') {
+            // NOTE: a parameter without value before this one is skipped
+            let name = name.rsplit(';').next().unwrap_or_default().trim();
+            let (value, after) = match after.strip_prefix('"') {
+                Some(quoted) => quoted_string(quoted),
+                None => {
+                    let (value, after) = after.split_once(';').unwrap_or((after, ""));
+                    (value.trim().to_string(), after)
+                }
+            };
+            rest = after;
+            if is_token(name) {
+                // NOTE: a repeated parameter is invalid, the first one is kept
+                params.entry(name.to_lowercase()).or_insert(value);
+            }
+        }
+        Some(Self { media_type, params })
+    }
+
+    /// for ex application/json, without the parameters
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+
+    /// Value of the parameter, the name is case insensitive
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(&name.to_lowercase()).map(|x| x.as_str())
+    }
+}
+
+/// Value of a quoted string whose opening quote is already removed, and what follows
+/// the closing quote up to the next parameter. A backslash escapes the next character
+fn quoted_string(s: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next().map(|(_, x)| x)),
+            '"' => {
+                let after = &s[i + 1..];
+                return (value, after.split_once(';').map_or("", |(_, x)| x));
+            }
+            c => value.push(c),
+        }
+    }
+    // NOTE: an unterminated string takes the rest of the header
+    (value, "")
+}
+
+/// Content type guessed from the first bytes of the content, used when the extension
+/// is unknown. Text is detected as valid utf8 without control characters
+/// https://mimesniff.spec.whatwg.org/#matching-an-image-type-pattern
+pub fn sniff_content_type(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: [(&[u8], &str); 7] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"%!PS", "application/postscript"),
+        (b"\x1f\x8b", "application/gzip"),
+    ];
+    if let Some((_, content_type)) = SIGNATURES.iter().find(|(x, _)| bytes.starts_with(x)) {
+        return Some(content_type);
+    }
+
+    // NOTE: the start of the content may end in the middle of a character
+    let text = match std::str::from_utf8(bytes) {
+        Ok(x) => x,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&bytes[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    let binary = text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'));
+    match !text.is_empty() && !binary {
+        true => Some("text/plain"),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn known_signatures_are_sniffed() {
+        let table: [(&[u8], Option<&str>); 12] = [
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", Some("image/png")),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", Some("image/jpeg")),
+            (b"GIF87a\x01\0", Some("image/gif")),
+            (b"GIF89a\x01\0", Some("image/gif")),
+            (b"%PDF-1.7\n", Some("application/pdf")),
+            (b"%!PS-Adobe-3.0\n", Some("application/postscript")),
+            (b"\x1f\x8b\x08\0", Some("application/gzip")),
+            (b"hello\tworld\r\n", Some("text/plain")),
+            // NOTE: the sniffed bytes may stop in the middle of a character
+            (
+                "caf\u{e9}".as_bytes().split_last().unwrap().1,
+                Some("text/plain"),
+            ),
+            (b"\0\x01\x02\x03", None),
+            (b"\xff\xfe\xfd", None),
+            (b"", None),
+        ];
+        for (bytes, expected) in table {
+            assert_eq!(sniff_content_type(bytes), expected, "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn content_type_splits_the_media_type_from_its_parameters() {
+        let content_type =
+            ContentType::parse("Application/JSON; charset=UTF-8; boundary=xyz").unwrap();
+        assert_eq!(content_type.media_type(), "application/json");
+        // NOTE: the names are case insensitive, the values are kept as is
+        assert_eq!(content_type.param("charset"), Some("UTF-8"));
+        assert_eq!(content_type.param("CHARSET"), Some("UTF-8"));
+        assert_eq!(content_type.param("boundary"), Some("xyz"));
+        assert_eq!(content_type.param("missing"), None);
+
+        let content_type = ContentType::parse("text/plain").unwrap();
+        assert_eq!(content_type.media_type(), "text/plain");
+        assert_eq!(content_type.param("charset"), None);
+    }
+
+    #[test]
+    fn content_type_parameters_can_be_quoted() {
+        let content_type =
+            ContentType::parse("multipart/form-data; boundary=\"a; b=\\\"c\\\"\"; charset = utf-8")
+                .unwrap();
+        assert_eq!(content_type.param("boundary"), Some("a; b=\"c\""));
+        assert_eq!(content_type.param("charset"), Some("utf-8"));
+
+        // NOTE: unterminated, the value takes the rest of the header
+        let content_type = ContentType::parse("text/plain; name=\"open; x=y").unwrap();
+        assert_eq!(content_type.param("name"), Some("open; x=y"));
+    }
+
+    #[test]
+    fn invalid_content_types_and_parameters() {
+        for invalid in [
+            "",
+            "json",
+            "application/",
+            "/json",
+            "text /plain",
+            "a/b/c d",
+        ] {
+            assert_eq!(ContentType::parse(invalid), None, "{:?}", invalid);
+        }
+        // NOTE: an invalid parameter is skipped, a repeated one keeps its first value
+        let content_type =
+            ContentType::parse("text/plain; flag; charset=utf-8; charset=latin1; =x").unwrap();
+        assert_eq!(content_type.param("charset"), Some("utf-8"));
+        assert_eq!(content_type.param("flag"), None);
+    }
+
+    #[test]
+    fn compressible_types_match_full_types_and_wildcards() {
+        let patterns = COMPRESSIBLE_TYPES.map(String::from);
+        let table = [
+            ("text/plain", true),
+            ("TEXT/HTML; charset=utf-8", true),
+            ("application/json", true),
+            ("image/svg+xml", true),
+            ("image/png", false),
+            ("application/zip", false),
+            ("application/octet-stream", false),
+            ("application/json-seq", false),
+            ("", false),
+        ];
+        for (content_type, expected) in table {
+            assert_eq!(
+                is_compressible(content_type, &patterns),
+                expected,
+                "{}",
+                content_type
+            );
+        }
+        assert!(is_compressible("image/png", &["image/*".to_string()]));
+        assert!(!is_compressible("text/plain", &[]));
+    }
+}
@@ -0,0 +1,85 @@
+use std::ops::Range;
+
+/// Result of evaluating a Range header against a representation of a given length
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    Satisfiable(Range<u64>),
+    Unsatisfiable,
+}
+
+/// Parses a single byte range: bytes=first-last, bytes=first- or bytes=-suffix.
+/// None means the header must be ignored and the full representation sent, which is
+/// the case for other units, malformed values and multiple ranges
+/// https://datatracker.ietf.org/doc/html/rfc9110#section-14.2
+pub fn parse_range(header: &str, len: u64) -> Option<ByteRange> {
+    let (unit, ranges) = header.trim().split_once('=')?;
+    // NOTE: range units are case insensitive
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    // NOTE: multipart/byteranges responses are not supported
+    if ranges.contains(',') {
+        return None;
+    }
+
+    let (first, last) = ranges.trim().split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let range = match (first.is_empty(), last.is_empty()) {
+        (true, true) => return None,
+        (true, false) => {
+            let suffix: u64 = last.parse().ok()?;
+            len.saturating_sub(suffix)..len
+        }
+        (false, _) => {
+            let first: u64 = first.parse().ok()?;
+            let last = match last.is_empty() {
+                true => u64::MAX,
+                false => last.parse().ok()?,
+            };
+            if last < first {
+                return None;
+            }
+            first..last.saturating_add(1).min(len)
+        }
+    };
+
+    match range.start < range.end {
+        true => Some(ByteRange::Satisfiable(range)),
+        false => Some(ByteRange::Unsatisfiable),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn byte_ranges_are_parsed() {
+        let table = [
+            ("bytes=0-4", Some(ByteRange::Satisfiable(0..5))),
+            ("bytes=5-", Some(ByteRange::Satisfiable(5..10))),
+            ("bytes=-3", Some(ByteRange::Satisfiable(7..10))),
+            ("BYTES = 2-100", Some(ByteRange::Satisfiable(2..10))),
+            ("bytes=-0", Some(ByteRange::Unsatisfiable)),
+            ("bytes=10-", Some(ByteRange::Unsatisfiable)),
+        ];
+        for (header, expected) in table {
+            assert_eq!(parse_range(header, 10), expected, "{}", header);
+        }
+    }
+
+    #[test]
+    fn other_units_and_malformed_ranges_are_ignored() {
+        for header in [
+            "items=0-9",
+            "bytes",
+            "bytes=-",
+            "bytes=4-2",
+            "bytes=a-b",
+            "bytes=0-1,3-4",
+        ] {
+            assert_eq!(parse_range(header, 10), None, "{}", header);
+        }
+    }
+}
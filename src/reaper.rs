@@ -0,0 +1,111 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::Shutdown,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::listener::Stream;
+
+/// Registry of the open connections with the time since which they are idle, so that
+/// the ones idle for too long can be closed from another thread
+#[derive(Clone, Default)]
+pub struct Connections {
+    inner: Arc<Mutex<Registry>>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    connections: HashMap<u64, Connection>,
+}
+
+struct Connection {
+    stream: Stream,
+    /// None while a request is handled
+    idle_since: Option<Instant>,
+}
+
+/// Keeps a connection in the registry until dropped
+pub struct Registration {
+    id: u64,
+    connections: Connections,
+}
+
+impl Connections {
+    /// The connection starts idle: it is not handled while waiting for a worker
+    pub fn register(&self, stream: &Stream) -> io::Result<Registration> {
+        let stream = stream.try_clone()?;
+        let mut registry = self
+            .inner
+            .lock()
+            .expect("Registry lock should not be poisoned");
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.connections.insert(
+            id,
+            Connection {
+                stream,
+                idle_since: Some(Instant::now()),
+            },
+        );
+        Ok(Registration {
+            id,
+            connections: self.clone(),
+        })
+    }
+
+    /// Closes the connections idle for longer than the timeout. The worker handling
+    /// one sees the end of the stream and returns
+    pub fn reap(&self, timeout: Duration) {
+        let mut registry = self
+            .inner
+            .lock()
+            .expect("Registry lock should not be poisoned");
+        registry.connections.retain(|_, connection| {
+            let idle = connection
+                .idle_since
+                .is_some_and(|idle_since| idle_since.elapsed() > timeout);
+            if idle {
+                println!("Closing connection idle for more than {:?}", timeout);
+                let _ = connection.stream.shutdown(Shutdown::Both);
+            }
+            !idle
+        });
+    }
+
+    fn set_idle_since(&self, id: u64, idle_since: Option<Instant>) {
+        let mut registry = self
+            .inner
+            .lock()
+            .expect("Registry lock should not be poisoned");
+        if let Some(connection) = registry.connections.get_mut(&id) {
+            connection.idle_since = idle_since;
+        }
+    }
+}
+
+impl Registration {
+    /// A request is being handled, the connection must not be reaped
+    pub fn busy(&self) {
+        self.connections.set_idle_since(self.id, None);
+    }
+
+    /// The response was sent, the connection waits for the next request
+    pub fn idle(&self) {
+        self.connections
+            .set_idle_since(self.id, Some(Instant::now()));
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut registry = self
+            .connections
+            .inner
+            .lock()
+            .expect("Registry lock should not be poisoned");
+        registry.connections.remove(&self.id);
+    }
+}
@@ -0,0 +1,364 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::config::Config;
+use crate::http::{HttpMethod, HttpRequest, HttpResponse};
+use crate::upgrade::{self, UpgradeHandler};
+use crate::{Error, Result};
+
+/// The request is mutable so that handlers can read its body
+pub type Handler = Arc<dyn Fn(&mut HttpRequest, &Config) -> Result<HttpResponse> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// must match exactly, for ex /echo
+    Static(String),
+    /// matches a single segment, for ex /:name
+    Param(String),
+    /// matches the remainder of the path, for ex /*rest. Only allowed last
+    Wildcard(String),
+}
+
+impl Segment {
+    // NOTE: used to pick the most specific route: exact > param > wildcard
+    fn rank(&self) -> u8 {
+        match self {
+            Self::Static(_) => 2,
+            Self::Param(_) => 1,
+            Self::Wildcard(_) => 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Route {
+    method: HttpMethod,
+    pattern: String,
+    segments: Vec<Segment>,
+    handler: Handler,
+    /// only set for the routes registered with Router::websocket
+    upgrade: Option<UpgradeHandler>,
+}
+
+impl Route {
+    fn specificity(&self) -> Vec<u8> {
+        self.segments.iter().map(Segment::rank).collect()
+    }
+
+    /// Returns the captured params if the path matches
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        let mut parts = path.strip_prefix('/')?.split('/');
+
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Static(x) => {
+                    if parts.next()? != x {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), parts.next()?.to_string());
+                }
+                Segment::Wildcard(name) => {
+                    let rest: Vec<_> = parts.by_ref().collect();
+                    if rest.is_empty() {
+                        return None;
+                    }
+                    params.insert(name.clone(), rest.join("/"));
+                }
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// pattern is a path where a segment can be :name to capture one segment or *name
+    /// (last segment only) to capture the remainder of the path. Captures are available
+    /// in HttpRequest.params
+    /// Registration order does not matter: segments are compared from left to right and
+    /// exact > param > wildcard, so /files/special takes priority over /files/*filename
+    pub fn route<F>(&mut self, method: HttpMethod, pattern: &str, handler: F) -> Result<&mut Self>
+    where
+        F: Fn(&mut HttpRequest, &Config) -> Result<HttpResponse> + Send + Sync + 'static,
+    {
+        let segments: Vec<_> = pattern
+            .strip_prefix('/')
+            .ok_or_else(|| Error::InvalidRoutePattern(pattern.to_string()))?
+            .split('/')
+            .map(|x| match x.chars().next() {
+                Some(':') => Segment::Param(x[1..].to_string()),
+                Some('*') => Segment::Wildcard(x[1..].to_string()),
+                _ => Segment::Static(x.to_string()),
+            })
+            .collect();
+
+        let wildcard_position = segments
+            .iter()
+            .position(|x| matches!(x, Segment::Wildcard(_)));
+        if wildcard_position.is_some_and(|x| x != segments.len() - 1) {
+            Err(Error::InvalidRoutePattern(pattern.to_string()))?;
+        }
+
+        self.routes.push(Route {
+            method,
+            pattern: pattern.to_string(),
+            segments,
+            handler: Arc::new(handler),
+            upgrade: None,
+        });
+        Ok(self)
+    }
+
+    /// Registers a GET route that accepts websocket upgrades, see Router::route for the
+    /// pattern. The upgrade handler receives the connection once the handshake is done,
+    /// a request to the route without upgrade gets a 426. Other routes are never
+    /// upgraded
+    pub fn websocket(&mut self, pattern: &str, upgrade: UpgradeHandler) -> Result<&mut Self> {
+        self.route(HttpMethod::Get, pattern, |_request, _config| {
+            Ok(upgrade::upgrade_required())
+        })?;
+        let route = self.routes.last_mut().expect("Route should be registered");
+        route.upgrade = Some(upgrade);
+        Ok(self)
+    }
+
+    /// Method and pattern of the registered routes, in registration order
+    pub fn routes(&self) -> impl Iterator<Item = (HttpMethod, &str)> {
+        self.routes
+            .iter()
+            .map(|route| (route.method.clone(), route.pattern.as_str()))
+    }
+
+    /// Finds the most specific route matching the method and path, along with the
+    /// captured params. On ties, the first registered route wins.
+    /// HEAD falls back to the GET route of the path if no HEAD route is registered,
+    /// the caller is responsible for discarding the body
+    pub fn find(
+        &self,
+        method: &HttpMethod,
+        path: &str,
+    ) -> Option<(&Handler, HashMap<String, String>)> {
+        match (self.find_exact(method, path), method) {
+            (None, HttpMethod::Head) => self.find_exact(&HttpMethod::Get, path),
+            (found, _) => found,
+        }
+    }
+
+    /// Method of the route find would use: GET for a HEAD request answered by the GET
+    /// route of the path, the method of the request otherwise
+    pub fn routed_method(&self, method: &HttpMethod, path: &str) -> HttpMethod {
+        match method {
+            HttpMethod::Head if self.find_exact(method, path).is_none() => HttpMethod::Get,
+            _ => method.clone(),
+        }
+    }
+
+    /// Methods of the routes matching the path, in registration order. HEAD is included
+    /// when GET is, see find. Empty if no route matches the path at all
+    pub fn allowed_methods(&self, path: &str) -> Vec<HttpMethod> {
+        let mut methods = Vec::new();
+        for route in self.routes.iter() {
+            if !methods.contains(&route.method) && route.matches(path).is_some() {
+                methods.push(route.method.clone());
+            }
+        }
+        if methods.contains(&HttpMethod::Get) && !methods.contains(&HttpMethod::Head) {
+            methods.push(HttpMethod::Head);
+        }
+        methods
+    }
+
+    /// Upgrade handler of the GET route matching the path, None if the path is not
+    /// routed to a websocket route
+    pub fn find_upgrade(&self, path: &str) -> Option<&UpgradeHandler> {
+        self.find_route(&HttpMethod::Get, path)
+            .and_then(|(route, _)| route.upgrade.as_ref())
+    }
+
+    fn find_exact(
+        &self,
+        method: &HttpMethod,
+        path: &str,
+    ) -> Option<(&Handler, HashMap<String, String>)> {
+        self.find_route(method, path)
+            .map(|(route, params)| (&route.handler, params))
+    }
+
+    fn find_route(
+        &self,
+        method: &HttpMethod,
+        path: &str,
+    ) -> Option<(&Route, HashMap<String, String>)> {
+        let mut best: Option<(&Route, HashMap<String, String>)> = None;
+        for route in self.routes.iter().filter(|x| &x.method == method) {
+            if let Some(params) = route.matches(path) {
+                let better = match &best {
+                    None => true,
+                    Some((current, _)) => route.specificity() > current.specificity(),
+                };
+                if better {
+                    best = Some((route, params));
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn ok(_request: &mut HttpRequest, _config: &Config) -> Result<HttpResponse> {
+        Ok(HttpResponse::no_content())
+    }
+
+    /// Pattern of the route found for the request, along with the captured params
+    fn find<'a>(
+        router: &'a Router,
+        method: HttpMethod,
+        path: &str,
+    ) -> Option<(&'a str, HashMap<String, String>)> {
+        let (handler, params) = router.find(&method, path)?;
+        let route = router
+            .routes
+            .iter()
+            .find(|route| Arc::ptr_eq(&route.handler, handler))?;
+        Some((route.pattern.as_str(), params))
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn routes_are_listed_in_registration_order() {
+        let mut router = Router::new();
+        router
+            .route(HttpMethod::Post, "/files/*filename", ok)
+            .unwrap();
+        router.route(HttpMethod::Get, "/", ok).unwrap();
+        router
+            .route(HttpMethod::Get, "/files/*filename", ok)
+            .unwrap();
+        router.route(HttpMethod::Get, "/echo/*message", ok).unwrap();
+
+        let expected = vec![
+            (HttpMethod::Post, "/files/*filename"),
+            (HttpMethod::Get, "/"),
+            (HttpMethod::Get, "/files/*filename"),
+            (HttpMethod::Get, "/echo/*message"),
+        ];
+        assert_eq!(router.routes().collect::<Vec<_>>(), expected);
+        assert_eq!(router.routes().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn head_is_routed_as_get_unless_it_has_its_own_route() {
+        let mut router = Router::new();
+        router
+            .route(HttpMethod::Get, "/files/*filename", ok)
+            .unwrap();
+        router.route(HttpMethod::Head, "/status", ok).unwrap();
+
+        assert_eq!(
+            router.routed_method(&HttpMethod::Head, "/files/a.txt"),
+            HttpMethod::Get
+        );
+        assert_eq!(
+            router.routed_method(&HttpMethod::Head, "/status"),
+            HttpMethod::Head
+        );
+        assert_eq!(
+            router.routed_method(&HttpMethod::Post, "/files/a.txt"),
+            HttpMethod::Post
+        );
+    }
+
+    #[test]
+    fn wildcard_captures_the_rest_of_the_path() {
+        let mut router = Router::new();
+        router.route(HttpMethod::Get, "/app/*rest", ok).unwrap();
+
+        assert_eq!(
+            find(&router, HttpMethod::Get, "/app/a/b/c"),
+            Some(("/app/*rest", params(&[("rest", "a/b/c")])))
+        );
+        // NOTE: a wildcard matches at least one segment
+        assert_eq!(find(&router, HttpMethod::Get, "/app"), None);
+        assert_eq!(find(&router, HttpMethod::Post, "/app/a"), None);
+    }
+
+    #[test]
+    fn exact_route_is_preferred_to_a_wildcard() {
+        for wildcard_first in [true, false] {
+            let mut router = Router::new();
+            let patterns = match wildcard_first {
+                true => ["/app/*rest", "/app/specific"],
+                false => ["/app/specific", "/app/*rest"],
+            };
+            for pattern in patterns {
+                router.route(HttpMethod::Get, pattern, ok).unwrap();
+            }
+
+            assert_eq!(
+                find(&router, HttpMethod::Get, "/app/specific"),
+                Some(("/app/specific", params(&[])))
+            );
+            assert_eq!(
+                find(&router, HttpMethod::Get, "/app/specific/more"),
+                Some(("/app/*rest", params(&[("rest", "specific/more")])))
+            );
+        }
+    }
+
+    #[test]
+    fn param_captures_a_single_segment() {
+        let mut router = Router::new();
+        router.route(HttpMethod::Get, "/users/:id", ok).unwrap();
+
+        assert_eq!(
+            find(&router, HttpMethod::Get, "/users/42"),
+            Some(("/users/:id", params(&[("id", "42")])))
+        );
+        assert_eq!(find(&router, HttpMethod::Get, "/users/42/posts"), None);
+    }
+
+    #[test]
+    fn only_websocket_routes_are_upgraded() {
+        let mut router = Router::new();
+        router.route(HttpMethod::Get, "/chat/lobby", ok).unwrap();
+        router.websocket("/chat/:room", Arc::new(|_| {})).unwrap();
+
+        assert!(router.find_upgrade("/chat/general").is_some());
+        // NOTE: the most specific route is not a websocket route
+        assert!(router.find_upgrade("/chat/lobby").is_none());
+        assert!(router.find_upgrade("/other").is_none());
+    }
+
+    #[test]
+    fn wildcard_must_be_the_last_segment() {
+        let mut router = Router::new();
+        assert!(router
+            .route(HttpMethod::Get, "/app/*rest/more", ok)
+            .is_err());
+        assert!(router.route(HttpMethod::Get, "app", ok).is_err());
+    }
+}
@@ -0,0 +1,841 @@
+use std::{
+    any::Any,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io::{self, BufRead, BufReader, BufWriter, Read},
+    net::{Shutdown, SocketAddr, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{Receiver, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::{ConcurrencyMode, Config};
+use crate::directory::Directory;
+use crate::http::{
+    canonicalize_header_name, HttpMethod, HttpRequest, HttpResponse, HttpStatus, HttpVersion,
+};
+use crate::listener::{self, Listener, Stream};
+use crate::reaper::{Connections, Registration};
+use crate::router::Router;
+use crate::threadpool::ThreadPool;
+use crate::upgrade::{self, UpgradeHandler};
+use crate::{tunnel, Error, HttpError, Result};
+
+/// How often the stop condition of Server::serve is checked
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// First wait after an accept error, see Backoff
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(5);
+
+/// How long a rejected connection is given to send its request, see Server::reject
+const REJECT_READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// A bound server, ready to accept connections. See Server::builder
+pub struct Server {
+    /// one per port, or the unix socket
+    listeners: Vec<Listener>,
+    /// None when each connection gets its own thread
+    pool: Option<ThreadPool>,
+    config: Arc<Config>,
+    /// connections handled or waiting for a worker
+    active: Arc<AtomicUsize>,
+    /// only filled when an idle timeout is configured, see reap_idle
+    connections: Connections,
+}
+
+/// Counts a connection as active until dropped, even if its handler panics
+struct ActiveConnection(Arc<AtomicUsize>);
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Collects the configuration and the routes of a Server. Errors, for ex an invalid
+/// route pattern, are returned by build
+#[derive(Default)]
+pub struct ServerBuilder {
+    config: Config,
+    error: Option<Error>,
+}
+
+impl ServerBuilder {
+    /// Replaces the whole configuration, including the router. Should be called first
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn directory(mut self, directory: &str) -> Self {
+        self.config.directory = Directory::new(directory);
+        self
+    }
+
+    /// 0 lets the os pick a free port
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.ports = vec![port];
+        self
+    }
+
+    /// Serves the same routes on each port, see port
+    pub fn ports(mut self, ports: &[u16]) -> Self {
+        self.config.ports = ports.to_vec();
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.threads = threads;
+        self
+    }
+
+    /// Replaces the routes registered so far, for ex with handlers::router()
+    pub fn router(mut self, router: Router) -> Self {
+        self.config.router = router;
+        self
+    }
+
+    /// See Router::route
+    pub fn route<F>(mut self, method: HttpMethod, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&mut HttpRequest, &Config) -> Result<HttpResponse> + Send + Sync + 'static,
+    {
+        if let Err(e) = self.config.router.route(method, pattern, handler) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    /// See Router::websocket
+    pub fn websocket(mut self, pattern: &str, upgrade: UpgradeHandler) -> Self {
+        if let Err(e) = self.config.router.websocket(pattern, upgrade) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    /// Binds the listener and starts the thread pool
+    pub fn build(self) -> Result<Server> {
+        if let Some(e) = self.error {
+            Err(e)?;
+        }
+        let mut config = self.config;
+        config.started = Instant::now();
+        if config.list_routes {
+            for (method, pattern) in config.router.routes() {
+                println!("{} {}", method.as_ref(), pattern);
+            }
+        }
+
+        // NOTE: bind actually behaves bind and listen from the socket api
+        let listeners = match &config.unix_socket {
+            #[cfg(unix)]
+            Some(path) => vec![listener::bind_unix(path)?],
+            #[cfg(not(unix))]
+            Some(_) => Err(io::Error::from(io::ErrorKind::Unsupported))?,
+            None => config
+                .ports
+                .iter()
+                .map(|&port| listener::bind(([127, 0, 0, 1], port).into(), config.backlog))
+                .collect::<Result<_>>()?,
+        };
+        if listeners.is_empty() {
+            Err(Error::NoPort)?;
+        }
+
+        let pool = match config.mode {
+            ConcurrencyMode::Pool => Some(ThreadPool::build(config.threads)?),
+            ConcurrencyMode::ThreadPerConnection => None,
+        };
+
+        Ok(Server {
+            listeners,
+            pool,
+            config: Arc::new(config),
+            active: Arc::new(AtomicUsize::new(0)),
+            connections: Connections::default(),
+        })
+    }
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Actual address the server listens on, for ex the port picked by the os when
+    /// bound to port 0. With several ports, the address of the first one
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listeners[0].local_addr()?)
+    }
+
+    /// Same as local_addr for each port, in the order they were given
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        Ok(self
+            .listeners
+            .iter()
+            .map(|x| x.local_addr())
+            .collect::<io::Result<_>>()?)
+    }
+
+    /// Accepts connections until draining is over, see Drain
+    pub fn run(self) -> Result<()> {
+        let config = Arc::clone(&self.config);
+        self.serve(move || config.drain.is_over(config.drain_grace_period))
+    }
+
+    /// Same as run but also stops when a value is sent on signal or its sender is
+    /// dropped
+    pub fn run_with_shutdown(self, signal: Receiver<()>) -> Result<()> {
+        let config = Arc::clone(&self.config);
+        self.serve(move || {
+            !matches!(signal.try_recv(), Err(TryRecvError::Empty))
+                || config.drain.is_over(config.drain_grace_period)
+        })
+    }
+
+    /// Accepts connections until stop returns true. Requests in progress are completed
+    /// before returning when using a thread pool, threads of the thread-per-conn mode
+    /// are left running. Each listener has its own accepting thread, the workers and
+    /// the configuration are shared
+    ///
+    /// An error of a listener itself, for ex a closed socket, stops the server and is
+    /// returned. Other accept errors, for ex running out of file descriptors, are
+    /// retried after a delay, see Backoff
+    fn serve<F: FnMut() -> bool + Send>(self, mut stop: F) -> Result<()> {
+        let stopping = AtomicBool::new(false);
+        let failure = Mutex::new(None);
+        thread::scope(|scope| {
+            let (stopping, failure, listeners, connections) =
+                (&stopping, &failure, &self.listeners, &self.connections);
+            scope.spawn(move || {
+                while !stop() && !stopping.load(Ordering::SeqCst) {
+                    thread::sleep(SHUTDOWN_POLL_INTERVAL);
+                }
+                stopping.store(true, Ordering::SeqCst);
+                // NOTE: accept is blocking, a connection wakes it up
+                for listener in listeners {
+                    if let Err(e) = listener.wake() {
+                        println!("Could not wake up the listener: {}", e);
+                    }
+                }
+            });
+            if let Some(timeout) = self.config.idle_timeout {
+                scope.spawn(move || reap_idle(connections, timeout, stopping));
+            }
+
+            for listener in listeners {
+                let server = &self;
+                scope.spawn(move || {
+                    let backoff = Backoff::new(server.config.accept_backoff_max);
+                    let fatal = accept_loop(
+                        listener.incoming(),
+                        backoff,
+                        stopping,
+                        |e| is_fatal(e, listener),
+                        |stream| server.accept(stream),
+                        thread::sleep,
+                    );
+                    if let Some(e) = fatal {
+                        println!("Listener failed: {}", e);
+                        *failure.lock().expect("Failure lock should not be poisoned") = Some(e);
+                        stopping.store(true, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+        println!("Shutting down server");
+        // NOTE: dropping the pool waits for its workers
+        drop(self.pool);
+        match failure
+            .into_inner()
+            .expect("Failure lock should not be poisoned")
+        {
+            Some(e) => Err(e)?,
+            None => Ok(()),
+        }
+    }
+
+    fn accept(&self, stream: Stream) {
+        let count = self.active.fetch_add(1, Ordering::SeqCst);
+        let active = ActiveConnection(Arc::clone(&self.active));
+        if self.config.max_connections.is_some_and(|max| count >= max) {
+            drop(active);
+            if let Err(e) = self.reject(stream) {
+                println!("error: {}", e);
+            }
+            return;
+        }
+        let registration = match self.config.idle_timeout {
+            Some(_) => match self.connections.register(&stream) {
+                Ok(registration) => Some(registration),
+                Err(e) => {
+                    println!("Could not register connection: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let config = Arc::clone(&self.config);
+        let job = move || {
+            let _active = active;
+            // NOTE: the other errors already got a response. An io error means
+            // the connection is unusable, for ex closed by the client or reaped
+            if let Err(e) = handle_connection(stream, &config, registration.as_ref()) {
+                println!("error: {}", e);
+            }
+        };
+        match &self.pool {
+            Some(pool) => pool.execute(job),
+            // NOTE: the join handle is dropped so the thread is detached
+            None => {
+                thread::spawn(job);
+            }
+        }
+    }
+
+    /// Sends a 503 with Retry-After from the accepting thread, without a worker. The
+    /// request is not handled but what is already received is read: closing a socket
+    /// with unread data resets the connection and the client could miss the response
+    fn reject(&self, mut stream: Stream) -> Result<()> {
+        stream.set_read_timeout(Some(REJECT_READ_TIMEOUT))?;
+        let _ = stream.read(&mut [0; 4096]);
+
+        let mut http_response = HttpResponse::empty_response(HttpStatus::ServiceUnavailable503);
+        let retry_after = self
+            .config
+            .retry_after_format
+            .format(self.config.retry_after, SystemTime::now());
+        http_response.set_header("Retry-After", &retry_after);
+        http_response.set_header("Connection", "close");
+        finalize_response(&mut http_response, &self.config);
+        http_response.write_to(&mut stream)?;
+        stream.shutdown(Shutdown::Write)?;
+        Ok(())
+    }
+}
+
+/// What to do with the connection once the request is handled
+enum Outcome {
+    Respond(HttpResponse),
+    /// the handshake response is sent, then the connection is given to the handler
+    Upgrade(HttpResponse, UpgradeHandler),
+    /// bytes are copied between the client and upstream
+    Tunnel(TcpStream),
+}
+
+/// Wait between accepts that keep failing, for ex while the process is out of file
+/// descriptors. Retrying right away would spin without giving the connections in
+/// progress a chance to close
+struct Backoff {
+    delay: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    fn new(max: Duration) -> Self {
+        Self {
+            delay: ACCEPT_BACKOFF_MIN.min(max),
+            max,
+        }
+    }
+
+    /// Doubles the wait after each failure, up to max
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.max);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.delay = ACCEPT_BACKOFF_MIN.min(self.max);
+    }
+}
+
+/// Accepts until stopping or until a fatal error, which is returned. The other errors
+/// are retried after the delay of the backoff. Generic over the accepted connections
+/// and the sleep so that the retries can be tested without a failing listener
+fn accept_loop<T>(
+    incoming: impl Iterator<Item = io::Result<T>>,
+    mut backoff: Backoff,
+    stopping: &AtomicBool,
+    is_fatal: impl Fn(&io::Error) -> bool,
+    mut accept: impl FnMut(T),
+    mut sleep: impl FnMut(Duration),
+) -> Option<io::Error> {
+    for stream in incoming {
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+        match stream {
+            Ok(stream) => {
+                backoff.reset();
+                accept(stream);
+            }
+            Err(e) if is_fatal(&e) => return Some(e),
+            Err(e) => {
+                let delay = backoff.next_delay();
+                println!("error: {}, accepting again in {:?}", e, delay);
+                sleep(delay);
+            }
+        }
+    }
+    None
+}
+
+/// True if the listener can not accept anymore, as opposed to an error with a single
+/// connection or a lack of resources that may go away. EINVAL means the socket is not
+/// listening
+fn is_fatal(error: &io::Error, listener: &Listener) -> bool {
+    error.kind() == io::ErrorKind::InvalidInput || listener.check().is_err()
+}
+
+/// Closes the connections idle for longer than the timeout until the server stops.
+/// Unlike the keep-alive timeout, it also covers connections waiting for a worker
+/// and clients that are slow to send their request
+fn reap_idle(connections: &Connections, timeout: Duration, stopping: &AtomicBool) {
+    while !stopping.load(Ordering::SeqCst) {
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        connections.reap(timeout);
+    }
+}
+
+/// Serves requests until the client or the server closes the connection. An idle
+/// connection is closed after the keep-alive timeout so that it does not hold a worker.
+/// The request timeout is checked once the handler returns: handlers are not
+/// interrupted, only their response is replaced. A response that is already being
+/// written, as well as upgraded and tunneled connections, are not affected
+fn handle_connection(
+    stream: Stream,
+    config: &Config,
+    registration: Option<&Registration>,
+) -> Result<()> {
+    let mut reader = BufReader::with_capacity(config.read_buffer_size, stream);
+    let mut served = 0;
+
+    loop {
+        if let Some(registration) = registration {
+            registration.idle();
+        }
+        if served > 0 {
+            reader
+                .get_ref()
+                .set_read_timeout(Some(config.keep_alive_timeout))?;
+        }
+        // NOTE: closed by the client or idle for too long
+        match reader.fill_buf() {
+            Ok([]) | Err(_) => return Ok(()),
+            Ok(_) => reader.get_ref().set_read_timeout(None)?,
+        }
+        served += 1;
+        let start = Instant::now();
+
+        let (outcome, keep_alive, http10) =
+            match HttpRequest::read_from(&mut reader, &config.parse_options) {
+                Ok(mut http_request) => {
+                    if let Some(registration) = registration {
+                        registration.busy();
+                    }
+                    let outcome = dispatch(&mut http_request, config);
+                    // NOTE: if the body is invalid, the response is still sent before
+                    // closing. A body that is not entirely read could not be told apart
+                    // from the next request
+                    let body_done =
+                        http_request.discard_body().is_ok() && http_request.body_reader().is_done();
+                    let keep_alive = http_request.wants_keep_alive() && body_done;
+                    (
+                        outcome,
+                        keep_alive,
+                        http_request.version == HttpVersion::V1_0,
+                    )
+                }
+                Err(Error::IoError(e)) => return Err(e.into()),
+                // NOTE: nothing to answer, the connection is closed silently
+                Err(Error::ConnectionClosed) => return Ok(()),
+                Err(e) => (Outcome::Respond(error_response(&e, config)), false, false),
+            };
+        // NOTE: bounds the resources a single pipelining client can use
+        let keep_alive = keep_alive && served < config.max_requests_per_connection;
+
+        match outcome {
+            Outcome::Respond(mut http_response) => {
+                if config
+                    .request_timeout
+                    .is_some_and(|timeout| start.elapsed() > timeout)
+                {
+                    println!("request timed out after {:?}", start.elapsed());
+                    http_response = HttpResponse::empty_response(HttpStatus::ServiceUnavailable503);
+                }
+                if config.fault_injection {
+                    inject_fault(&mut http_response, config);
+                }
+                finalize_response(&mut http_response, config);
+                // NOTE: a safety net against a handler producing huge headers, the
+                // client gets an error rather than a response it may not be able to parse
+                let headers_size = http_response.headers_size();
+                if headers_size > config.max_response_headers_size {
+                    println!(
+                        "error: response headers of {} bytes exceed the maximum size",
+                        headers_size
+                    );
+                    http_response = HttpResponse::internal_error();
+                    finalize_response(&mut http_response, config);
+                }
+                // NOTE: measured last so that it includes compression and fault injection,
+                // a streamed body is still being read from the handler's source
+                if config.server_timing {
+                    let duration = start.elapsed().as_secs_f64() * 1000.0;
+                    let server_timing = format!("total;dur={:.3}", duration);
+                    http_response.set_header("Server-Timing", &server_timing);
+                }
+                let close_delimited = http_response.is_close_delimited();
+                let keep_alive = keep_alive && !close_delimited;
+                match (keep_alive, http10) {
+                    (false, _) => http_response.set_header("Connection", "close"),
+                    (true, true) => http_response.set_header("Connection", "keep-alive"),
+                    (true, false) => {}
+                }
+
+                let mut writer =
+                    BufWriter::with_capacity(config.write_buffer_size, reader.get_mut());
+                http_response.write_to(&mut writer)?;
+                let stream = writer.into_inner().map_err(|e| e.into_error())?;
+                if close_delimited {
+                    stream.shutdown(Shutdown::Write)?;
+                }
+                if !keep_alive {
+                    return Ok(());
+                }
+            }
+            Outcome::Upgrade(mut http_response, upgrade_handler) => {
+                http_response.write_to(reader.get_mut())?;
+                // NOTE: from now on, the connection no longer speaks http. The timeout
+                // left by the request read timeout no longer applies
+                reader.get_ref().set_read_timeout(None)?;
+                upgrade_handler(reader);
+                return Ok(());
+            }
+            Outcome::Tunnel(upstream) => {
+                // NOTE: a 2xx response to CONNECT must not contain a Content-Length
+                let mut http_response =
+                    HttpResponse::empty_response(HttpStatus::ConnectionEstablished200);
+                http_response.remove_header("Content-Length");
+                http_response.write_to(reader.get_mut())?;
+                tunnel::tunnel(reader, upstream, config.tunnel_idle_timeout)?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Delays the response and replaces it by a 500 at random, for testing clients. The
+/// handler already ran, as when the response is lost after the request was processed
+fn inject_fault(http_response: &mut HttpResponse, config: &Config) {
+    if let Some(latency) = config.inject_latency {
+        thread::sleep(latency);
+    }
+    if random_fraction() < config.inject_failure_rate {
+        println!("Injecting a failure");
+        *http_response = HttpResponse::internal_error();
+    }
+}
+
+/// Number in [0, 1). NOTE: each RandomState is seeded differently, which is enough
+/// for fault injection without depending on a random crate
+fn random_fraction() -> f64 {
+    let hash = RandomState::new().build_hasher().finish();
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Headers added to every response, including errors, just before it is written
+fn finalize_response(http_response: &mut HttpResponse, config: &Config) {
+    if config.security_headers {
+        http_response.set_header("X-Content-Type-Options", "nosniff");
+        http_response.set_header("X-Frame-Options", "DENY");
+    }
+    if let Some(policy) = &config.content_security_policy {
+        http_response.set_header("Content-Security-Policy", policy);
+    }
+    // NOTE: only the headers set by the handler are skipped or replaced, an extra
+    // header given several times is sent several times
+    let mut present: Vec<_> = http_response
+        .headers
+        .iter()
+        .map(|header| header.key.to_lowercase())
+        .collect();
+    for header in config.extra_headers.iter() {
+        let key = header.key.to_lowercase();
+        if present.contains(&key) {
+            if !config.override_headers {
+                continue;
+            }
+            http_response.remove_header(&header.key);
+            present.retain(|x| *x != key);
+        }
+        http_response.headers.push(header.clone());
+    }
+    if config.canonical_header_names {
+        for header in http_response.headers.iter_mut() {
+            header.key = canonicalize_header_name(&header.key);
+        }
+    }
+}
+
+fn dispatch(http_request: &mut HttpRequest, config: &Config) -> Outcome {
+    handle_request(http_request, config)
+        .unwrap_or_else(|e| Outcome::Respond(error_response(&e, config)))
+}
+
+/// Remaps a POST to the method given by X-HTTP-Method-Override or the _method query
+/// parameter, the header taking precedence. Only PUT and DELETE can be requested so that
+/// the override can not turn a POST into a safe method, which caches and the acl would
+/// treat differently
+fn override_method(http_request: &mut HttpRequest) -> Result<()> {
+    if http_request.method != HttpMethod::Post {
+        return Ok(());
+    }
+    let method = match http_request.header("x-http-method-override") {
+        Some(x) => x.trim().to_string(),
+        None => match http_request.query.get("_method") {
+            Some(x) => x.clone(),
+            None => return Ok(()),
+        },
+    };
+    match HttpMethod::from_str(&method) {
+        Ok(method @ (HttpMethod::Put | HttpMethod::Delete)) => http_request.method = method,
+        _ => Err(HttpError::bad_request(&format!(
+            "Method override to {} is not allowed",
+            method
+        )))?,
+    }
+    Ok(())
+}
+
+/// Internal errors are logged with an id that is also sent to the client so that a
+/// failure can be found in the log. A 413 tells the client the limit so that it can
+/// retry with a smaller body. Other errors are the client's fault and get an empty
+/// response
+fn error_response(error: &Error, config: &Config) -> HttpResponse {
+    if let Error::Http(e) = error {
+        return e.clone().into();
+    }
+    match HttpStatus::from(error) {
+        HttpStatus::InternalServerError500 => {
+            static COUNTER: AtomicU64 = AtomicU64::new(0);
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_millis())
+                .unwrap_or(0);
+            let id = format!(
+                "{:x}-{:x}",
+                timestamp,
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            println!("error {}: {:?}", id, error);
+
+            let mut http_response =
+                HttpResponse::content_response(&format!("Internal error: {}", id), "text/plain");
+            http_response.status = HttpStatus::InternalServerError500;
+            http_response
+        }
+        HttpStatus::PayloadTooLarge413 => {
+            let max_body_size = config.parse_options.max_body_size;
+            let mut http_response = HttpResponse::content_response(
+                &format!(
+                    "Request body exceeds the maximum size of {} bytes",
+                    max_body_size
+                ),
+                "text/plain",
+            );
+            http_response.status = HttpStatus::PayloadTooLarge413;
+            http_response.set_header("X-Max-Body-Size", &max_body_size.to_string());
+            http_response
+        }
+        status => HttpResponse::empty_response(status),
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "unknown panic payload".to_string(),
+    }
+}
+
+/// 405 listing the methods of the routes matching the path
+fn method_not_allowed(methods: &[HttpMethod]) -> HttpResponse {
+    let allow: Vec<_> = methods.iter().map(|x| x.as_ref()).collect();
+    let mut http_response = HttpResponse::empty_response(HttpStatus::MethodNotAllowed405);
+    http_response.set_header("Allow", &allow.join(", "));
+    http_response
+}
+
+/// Handshake of an upgrade to a websocket route. The handshake is a GET, and a client
+/// of another version of the protocol is told the one the server speaks
+/// https://datatracker.ietf.org/doc/html/rfc6455#section-4.2.1
+fn upgrade(http_request: &HttpRequest, upgrade_handler: &UpgradeHandler) -> Outcome {
+    if http_request.method != HttpMethod::Get {
+        return Outcome::Respond(method_not_allowed(&[HttpMethod::Get]));
+    }
+    if !upgrade::is_supported_version(http_request) {
+        return Outcome::Respond(upgrade::upgrade_required());
+    }
+    match upgrade::handshake_response(http_request) {
+        Some(http_response) => Outcome::Upgrade(http_response, upgrade_handler.clone()),
+        None => Outcome::Respond(HttpResponse::bad_request("Invalid websocket handshake")),
+    }
+}
+
+/// Opens the connection to the authority of a CONNECT request, ie host:port. The acl
+/// already allowed it
+fn connect(http_request: &HttpRequest) -> Outcome {
+    match TcpStream::connect(&http_request.path) {
+        Ok(upstream) => Outcome::Tunnel(upstream),
+        Err(_) => Outcome::Respond(HttpResponse::empty_response(HttpStatus::BadGateway502)),
+    }
+}
+
+fn handle_request(http_request: &mut HttpRequest, config: &Config) -> Result<Outcome> {
+    if config.allow_method_override {
+        override_method(http_request)?;
+    }
+    if http_request.method == HttpMethod::Connect && !config.enable_connect {
+        return Ok(Outcome::Respond(HttpResponse::empty_response(
+            HttpStatus::MethodNotAllowed405,
+        )));
+    }
+    // acl is evaluated before any handler
+    // NOTE: a HEAD answered by the GET route is also checked as a GET, otherwise denying
+    // GET would still leak the headers of the resource
+    let routed_method = config
+        .router
+        .routed_method(&http_request.method, &http_request.path);
+    if !config
+        .acl
+        .is_allowed(&http_request.method, &http_request.path)
+        || !config.acl.is_allowed(&routed_method, &http_request.path)
+    {
+        return Ok(Outcome::Respond(HttpResponse::empty_response(
+            HttpStatus::Forbidden403,
+        )));
+    }
+    if http_request.method == HttpMethod::Connect {
+        return Ok(connect(http_request));
+    }
+    // NOTE: only the routes registered with Router::websocket are upgraded, the others
+    // ignore the Upgrade header and answer as usual
+    if upgrade::is_websocket_upgrade(http_request) {
+        if let Some(upgrade_handler) = config.router.find_upgrade(&http_request.path) {
+            return Ok(upgrade(http_request, upgrade_handler));
+        }
+    }
+
+    match config.router.find(&http_request.method, &http_request.path) {
+        Some((handler, params)) => {
+            http_request.params = params;
+            // NOTE: the response is written after the handler returns so a panic can not
+            // leave a partial response on the connection
+            let mut http_response =
+                panic::catch_unwind(AssertUnwindSafe(|| handler(http_request, config)))
+                    .map_err(|payload| Error::HandlerPanicked(panic_message(&*payload)))??;
+            http_response.add_compression(
+                http_request.header("accept-encoding"),
+                &config.encoding_priority,
+                config.compression_level,
+                &config.compressible_types,
+            )?;
+            if http_request.version == HttpVersion::V1_1 {
+                http_response.use_chunked();
+            }
+            // NOTE: headers, including Content-Length, are the ones GET would send
+            if http_request.method == HttpMethod::Head {
+                http_response.body = None;
+                http_response.stream = None;
+            }
+            Ok(Outcome::Respond(http_response))
+        }
+        None => {
+            // NOTE: like the other rejections, it is sent before reading the body so a
+            // client waiting for 100 Continue does not send it
+            let methods = config.router.allowed_methods(&http_request.path);
+            match methods.is_empty() {
+                true => Ok(Outcome::Respond(HttpResponse::not_found())),
+                false => Ok(Outcome::Respond(method_not_allowed(&methods))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn backoff_doubles_up_to_its_max_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(30));
+        let delays: Vec<_> = (0..4).map(|_| backoff.next_delay()).collect();
+        assert_eq!(delays, [5, 10, 20, 30].map(Duration::from_millis).to_vec());
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), ACCEPT_BACKOFF_MIN);
+
+        // NOTE: the max also caps the first delay
+        let mut backoff = Backoff::new(Duration::from_millis(1));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn transient_accept_errors_are_retried_with_backoff_until_a_fatal_one() {
+        let transient = || Err(io::Error::from_raw_os_error(24));
+        let incoming = vec![
+            transient(),
+            transient(),
+            transient(),
+            Ok(1),
+            transient(),
+            Ok(2),
+            Err(io::Error::from(io::ErrorKind::InvalidInput)),
+            Ok(3),
+        ];
+        let (mut accepted, mut delays) = (Vec::new(), Vec::new());
+
+        let fatal = accept_loop(
+            incoming.into_iter(),
+            Backoff::new(Duration::from_secs(1)),
+            &AtomicBool::new(false),
+            |e| e.kind() == io::ErrorKind::InvalidInput,
+            |x| accepted.push(x),
+            |delay| delays.push(delay),
+        );
+
+        assert_eq!(fatal.map(|e| e.kind()), Some(io::ErrorKind::InvalidInput));
+        assert_eq!(accepted, [1, 2]);
+        // NOTE: the successful accept resets the backoff
+        assert_eq!(delays, [5, 10, 20, 5].map(Duration::from_millis).to_vec());
+    }
+
+    #[test]
+    fn accept_loop_stops_once_stopping() {
+        let mut accepted = Vec::new();
+        let fatal = accept_loop(
+            vec![Ok(1)].into_iter(),
+            Backoff::new(Duration::from_secs(1)),
+            &AtomicBool::new(true),
+            |_| true,
+            |x| accepted.push(x),
+            |_| {},
+        );
+        assert!(fatal.is_none());
+        assert!(accepted.is_empty());
+    }
+}
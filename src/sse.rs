@@ -0,0 +1,93 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use crate::http::HttpResponse;
+
+/// Sends server-sent events on a text/event-stream response, see event_stream
+/// https://html.spec.whatwg.org/multipage/server-sent-events.html
+pub struct EventWriter<'a> {
+    writer: &'a mut dyn Write,
+}
+
+impl EventWriter<'_> {
+    /// Sends the event right away. Each line of data becomes a data field, the client
+    /// joins them back with newlines
+    pub fn send(&mut self, data: &str) -> io::Result<()> {
+        for line in data.split('\n') {
+            writeln!(self.writer, "data: {}", line.trim_end_matches('\r'))?;
+        }
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+/// Event streams in progress, shared with the handlers. A stream holds its worker until
+/// the client disconnects so too many of them would starve the other requests
+#[derive(Debug, Clone, Default)]
+pub struct EventStreams {
+    active: Arc<AtomicUsize>,
+}
+
+/// Counts a stream as in progress until dropped, even if the client is gone
+pub struct EventStream {
+    streams: EventStreams,
+}
+
+impl EventStreams {
+    /// None if max streams are already in progress
+    pub fn start(&self, max: usize) -> Option<EventStream> {
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| {
+                (x < max).then_some(x + 1)
+            })
+            .ok()?;
+        Some(EventStream {
+            streams: self.clone(),
+        })
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.streams.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Response whose events are sent by f. The stream ends when f returns, or when an
+/// event can not be sent because the client is gone
+pub fn event_stream<F>(f: F) -> HttpResponse
+where
+    F: FnOnce(&mut EventWriter) -> io::Result<()> + Send + 'static,
+{
+    let mut response = HttpResponse::writer_response(
+        move |writer| f(&mut EventWriter { writer }),
+        "text/event-stream",
+    );
+    // NOTE: the events are not meant to be replayed by a cache
+    response.set_header("Cache-Control", "no-cache");
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_streams_are_limited_until_dropped() {
+        let streams = EventStreams::default();
+
+        let first = streams.start(2);
+        let second = streams.start(2);
+        assert!(first.is_some() && second.is_some());
+        assert!(streams.start(2).is_none());
+
+        drop(first);
+        assert!(streams.start(2).is_some());
+        assert!(streams.start(0).is_none());
+    }
+}
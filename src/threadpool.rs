@@ -58,6 +58,8 @@ impl Drop for ThreadPool {
 }
 
 struct Worker {
+    // NOTE: only read by the debug print of drop
+    #[allow(dead_code)]
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
 }
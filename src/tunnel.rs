@@ -0,0 +1,133 @@
+use std::{
+    io::{self, BufReader, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::listener::Stream;
+use crate::Result;
+
+/// Shared by both directions of a tunnel, it is only idle when neither side sends
+struct Activity {
+    last: Mutex<Instant>,
+    /// set once the tunnel is closed for being idle, the errors that follow are expected
+    closed: AtomicBool,
+}
+
+impl Activity {
+    fn touch(&self) {
+        *self
+            .last
+            .lock()
+            .expect("Activity lock should not be poisoned") = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last
+            .lock()
+            .expect("Activity lock should not be poisoned")
+            .elapsed()
+    }
+}
+
+/// Copies bytes in both directions until either side closes. The client is given as
+/// a buffered reader because bytes sent right after the CONNECT request may already
+/// be buffered, they are forwarded first.
+/// A tunnel where nothing is sent in either direction for idle_timeout is closed, so
+/// that idle tunnels do not hold their worker forever. It is checked on each read
+/// timeout, so the tunnel is closed between one and two idle_timeout after the last byte
+pub fn tunnel(
+    mut client: BufReader<Stream>,
+    mut upstream: TcpStream,
+    idle_timeout: Duration,
+) -> Result<()> {
+    client.get_ref().set_read_timeout(Some(idle_timeout))?;
+    upstream.set_read_timeout(Some(idle_timeout))?;
+    let mut client_writer = client.get_ref().try_clone()?;
+    let mut upstream_reader = upstream.try_clone()?;
+    let activity = Activity {
+        last: Mutex::new(Instant::now()),
+        closed: AtomicBool::new(false),
+    };
+    // NOTE: shutting both sockets down wakes up the reads of the other direction
+    let close = |client: &Stream, upstream: &TcpStream| {
+        activity.closed.store(true, Ordering::SeqCst);
+        let _ = client.shutdown(Shutdown::Both);
+        let _ = upstream.shutdown(Shutdown::Both);
+    };
+
+    let (copied, downstream) = thread::scope(|scope| {
+        let downstream = scope.spawn(|| {
+            let copied = copy_until_idle(
+                &mut upstream_reader,
+                &mut client_writer,
+                &activity,
+                idle_timeout,
+            );
+            match copied {
+                Ok(false) => close(&client_writer, &upstream_reader),
+                // NOTE: tells the client that upstream closed its side
+                _ => {
+                    let _ = client_writer.shutdown(Shutdown::Write);
+                }
+            }
+            copied
+        });
+
+        let copied = copy_until_idle(&mut client, &mut upstream, &activity, idle_timeout);
+        match copied {
+            Ok(false) => close(client.get_ref(), &upstream),
+            _ => {
+                let _ = upstream.shutdown(Shutdown::Write);
+            }
+        }
+        let downstream = downstream.join().expect("Tunnel thread should not panic");
+        (copied, downstream)
+    });
+
+    if activity.closed.load(Ordering::SeqCst) {
+        println!("Closing tunnel idle for more than {:?}", idle_timeout);
+        return Ok(());
+    }
+    downstream?;
+    copied?;
+    Ok(())
+}
+
+/// Copies until the reader closes, returns true, or until the tunnel is idle, returns
+/// false
+fn copy_until_idle(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    activity: &Activity,
+    idle_timeout: Duration,
+) -> io::Result<bool> {
+    let mut buf = [0; 8 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(true),
+            Ok(n) => {
+                writer.write_all(&buf[..n])?;
+                activity.touch();
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            // NOTE: the other direction may still be active
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                if activity.idle_for() >= idle_timeout {
+                    return Ok(false);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
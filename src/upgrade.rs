@@ -0,0 +1,79 @@
+use std::{io::BufReader, sync::Arc};
+
+use base64::Engine;
+use sha1::{Digest, Sha1};
+
+use crate::http::{HttpRequest, HttpResponse, HttpStatus};
+use crate::listener::Stream;
+
+// https://datatracker.ietf.org/doc/html/rfc6455#section-1.3
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only version of the protocol, earlier ones were drafts
+const WEBSOCKET_VERSION: &str = "13";
+
+/// Called with the connection once the 101 response is sent. The buffered reader is
+/// handed over as is because it may already contain the first frames sent by the client.
+pub type UpgradeHandler = Arc<dyn Fn(BufReader<Stream>) + Send + Sync>;
+
+/// True if the request contains both Upgrade: websocket and Connection: Upgrade.
+/// Connection is a comma separated list, for ex `keep-alive, Upgrade`
+pub fn is_websocket_upgrade(request: &HttpRequest) -> bool {
+    let upgrade = request
+        .header("upgrade")
+        .is_some_and(|x| x.eq_ignore_ascii_case("websocket"));
+    let connection = request.header("connection").is_some_and(|x| {
+        x.split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+    });
+    upgrade && connection
+}
+
+/// True if the client speaks the version of the protocol the server implements
+/// https://datatracker.ietf.org/doc/html/rfc6455#section-4.4
+pub fn is_supported_version(request: &HttpRequest) -> bool {
+    request
+        .header("sec-websocket-version")
+        .is_some_and(|x| x.trim() == WEBSOCKET_VERSION)
+}
+
+/// 426 telling the client to retry with a websocket handshake of the supported version
+pub fn upgrade_required() -> HttpResponse {
+    let mut response = HttpResponse::empty_response(HttpStatus::UpgradeRequired426);
+    response.set_header("Upgrade", "websocket");
+    response.set_header("Connection", "Upgrade");
+    response.set_header("Sec-WebSocket-Version", WEBSOCKET_VERSION);
+    response
+}
+
+pub fn websocket_accept(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+}
+
+/// Returns None if the Sec-WebSocket-Key header is missing
+pub fn handshake_response(request: &HttpRequest) -> Option<HttpResponse> {
+    let key = request.header("sec-websocket-key")?;
+    let mut response = HttpResponse::new(HttpStatus::SwitchingProtocols101);
+    response.set_header("Upgrade", "websocket");
+    response.set_header("Connection", "Upgrade");
+    response.set_header("Sec-WebSocket-Accept", &websocket_accept(key));
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn websocket_accept_of_the_rfc_example() {
+        // https://datatracker.ietf.org/doc/html/rfc6455#section-1.3
+        assert_eq!(
+            websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+}
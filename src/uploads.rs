@@ -0,0 +1,74 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+/// Uploads in progress per client ip, shared with the handlers so that a single client
+/// can not saturate the disk with concurrent uploads
+#[derive(Debug, Clone, Default)]
+pub struct Uploads {
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+/// Counts an upload as in progress until dropped, even if the handler fails
+pub struct Upload {
+    ip: IpAddr,
+    uploads: Uploads,
+}
+
+impl Uploads {
+    /// None if the client already has max uploads in progress
+    pub fn start(&self, ip: IpAddr, max: usize) -> Option<Upload> {
+        let mut active = self
+            .active
+            .lock()
+            .expect("Uploads lock should not be poisoned");
+        let count = active.entry(ip).or_insert(0);
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(Upload {
+            ip,
+            uploads: self.clone(),
+        })
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) {
+        let mut active = self
+            .uploads
+            .active
+            .lock()
+            .expect("Uploads lock should not be poisoned");
+        if let Some(count) = active.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_are_limited_per_ip_until_dropped() {
+        let uploads = Uploads::default();
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+
+        let first = uploads.start(a, 2);
+        let second = uploads.start(a, 2);
+        assert!(first.is_some() && second.is_some());
+        assert!(uploads.start(a, 2).is_none());
+        // NOTE: other clients have their own count
+        assert!(uploads.start(b, 2).is_some());
+
+        drop(first);
+        assert!(uploads.start(a, 2).is_some());
+    }
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::{TempDir, TestServer};
+
+#[test]
+fn denied_method_is_rejected_while_others_are_served() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "hello");
+    let server = TestServer::start(&["--directory", dir.arg(), "--deny", "POST:/files/"]);
+
+    let response = server.request(
+        "POST /files/b.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 3\r\n\r\nabc",
+    );
+    assert_eq!(response.status, 403);
+    assert!(!dir.path().join("b.txt").exists());
+
+    let response = server.get("/files/a.txt");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "hello");
+}
+
+#[test]
+fn allow_rule_takes_precedence_over_deny_rule() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&[
+        "--directory",
+        dir.arg(),
+        "--deny",
+        "POST:/files/",
+        "--allow",
+        "POST:/files/public/",
+    ]);
+    std::fs::create_dir(dir.path().join("public")).unwrap();
+
+    let response = server.request(
+        "POST /files/public/b.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 3\r\n\r\nabc",
+    );
+    assert_eq!(response.status, 201);
+    assert_eq!(
+        std::fs::read(dir.path().join("public/b.txt")).unwrap(),
+        b"abc"
+    );
+}
+
+#[test]
+fn head_is_denied_when_the_get_it_falls_back_to_is_denied() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "hello");
+    let server = TestServer::start(&["--directory", dir.arg(), "--deny", "GET:/files/"]);
+
+    let mut client = server.connect();
+    client.send(b"HEAD /files/a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let response = client.read_head_response();
+    assert_eq!(response.status, 403);
+    assert_eq!(response.header("etag"), None);
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::{config, TempDir, TestServer};
+use http_server_starter_rust::http::{HttpMethod, HttpResponse};
+use http_server_starter_rust::{handlers, Server};
+
+/// Request to an admin route with the token, if any
+fn admin_request(path: &str, token: Option<&str>) -> String {
+    let token = token
+        .map(|x| format!("X-Admin-Token: {}\r\n", x))
+        .unwrap_or_default();
+    format!(
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 0\r\n{}\r\n",
+        path, token
+    )
+}
+
+#[test]
+fn drain_fails_health_checks_serves_requests_then_stops() {
+    let builder = Server::builder()
+        .config(config(&["--drain-grace-period", "1s"]))
+        .router(handlers::router().unwrap())
+        .route(HttpMethod::Get, "/slow", |_request, _config| {
+            thread::sleep(Duration::from_millis(500));
+            Ok(HttpResponse::content_response("completed", "text/plain"))
+        });
+    let server = TestServer::with_builder(builder);
+    assert_eq!(server.get("/healthz").status, 200);
+
+    let mut in_flight = server.connect();
+    in_flight.send(b"GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    // NOTE: lets the server pick up the request before draining starts
+    thread::sleep(Duration::from_millis(100));
+    let response = server.request(&admin_request("/_drain", None));
+    assert_eq!(response.status, 202);
+
+    let response = server.get("/healthz");
+    assert_eq!(response.status, 503);
+    assert_eq!(response.text(), "draining");
+    let response = server.get("/echo/still-served");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "still-served");
+
+    let response = in_flight.read_response();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "completed");
+
+    server.join().unwrap();
+}
+
+#[test]
+fn drain_requires_the_admin_token_when_one_is_set() {
+    let server = TestServer::start(&["--admin-token", "secret"]);
+    for token in [None, Some("wrong")] {
+        let response = server.request(&admin_request("/_drain", token));
+        assert_eq!(response.status, 403);
+    }
+    assert_eq!(server.get("/healthz").status, 200);
+
+    let response = server.request(&admin_request("/_drain", Some("secret")));
+    assert_eq!(response.status, 202);
+    assert_eq!(server.get("/healthz").status, 503);
+}
+
+#[test]
+fn reload_serves_files_from_the_new_directory() {
+    let (old, new) = (TempDir::new(), TempDir::new());
+    old.write("a.txt", "old");
+    new.write("a.txt", "new");
+    let server = TestServer::start(&["--directory", old.arg()]);
+    assert_eq!(server.get("/files/a.txt").text(), "old");
+
+    let reload = format!("/_reload?directory={}", new.arg());
+    let response = server.request(&admin_request(&reload, None));
+    assert_eq!(response.status, 204);
+    assert_eq!(server.get("/files/a.txt").text(), "new");
+
+    let missing = format!("/_reload?directory={}/missing", old.arg());
+    let response = server.request(&admin_request(&missing, None));
+    assert_eq!(response.status, 400);
+    assert_eq!(server.get("/files/a.txt").text(), "new");
+}
+
+#[test]
+fn reload_requires_the_admin_token_when_one_is_set() {
+    let (old, new) = (TempDir::new(), TempDir::new());
+    old.write("a.txt", "old");
+    new.write("a.txt", "new");
+    let reload = format!("/_reload?directory={}", new.arg());
+
+    // NOTE: being a loopback client is not enough, proxied clients are too
+    let server = TestServer::start(&["--directory", old.arg(), "--admin-token", "secret"]);
+    for token in [None, Some("wrong")] {
+        assert_eq!(server.request(&admin_request(&reload, token)).status, 403);
+    }
+    assert_eq!(server.get("/files/a.txt").text(), "old");
+
+    let response = server.request(&admin_request(&reload, Some("secret")));
+    assert_eq!(response.status, 204);
+    assert_eq!(server.get("/files/a.txt").text(), "new");
+}
+
+#[test]
+fn shutdown_requires_the_admin_token_then_stops_the_server() {
+    let server = TestServer::start(&[]);
+    let response = server.request(&admin_request("/_shutdown", Some("secret")));
+    assert_eq!(response.status, 404);
+
+    let server = TestServer::start(&["--admin-token", "secret"]);
+    for token in [None, Some("wrong")] {
+        let response = server.request(&admin_request("/_shutdown", token));
+        assert_eq!(response.status, 403);
+    }
+    assert_eq!(server.get("/healthz").status, 200);
+
+    let response = server.request(&admin_request("/_shutdown", Some("secret")));
+    assert_eq!(response.status, 202);
+    // NOTE: join returns once the server stopped by itself, the test still holds the
+    // shutdown channel
+    server.join().unwrap();
+}
@@ -0,0 +1,61 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::os::unix::fs::PermissionsExt;
+
+use common::{TempDir, TestServer};
+
+/// Writes an executable script in the directory
+fn script(dir: &TempDir, name: &str, content: &str) {
+    let path = dir.write(name, content);
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn echo_script_gets_the_request_and_sets_the_response() {
+    let dir = TempDir::new();
+    script(
+        &dir,
+        "echo.sh",
+        "#!/bin/sh\n\
+         printf 'Status: 201 Created\\r\\nContent-Type: text/plain\\r\\nX-Method: %s\\r\\n\\r\\n' \"$REQUEST_METHOD\"\n\
+         echo \"agent=$HTTP_USER_AGENT\"\n\
+         echo \"length=$CONTENT_LENGTH\"\n\
+         echo \"query=$QUERY_STRING\"\n\
+         echo \"proxy=${HTTP_PROXY-unset}\"\n\
+         cat\n",
+    );
+    let server = TestServer::start(&["--cgi-dir", dir.arg()]);
+
+    let response = server.request(
+        "POST /cgi/echo.sh?a=1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nUser-Agent: test\r\nProxy: http://attacker:8080\r\nContent-Length: 5\r\n\r\nhello",
+    );
+    assert_eq!(response.status, 201);
+    assert_eq!(response.header("content-type"), Some("text/plain"));
+    assert_eq!(response.header("x-method"), Some("POST"));
+    assert_eq!(
+        response.text(),
+        "agent=test\nlength=5\nquery=a=1\nproxy=unset\nhello"
+    );
+}
+
+#[test]
+fn scripts_outside_the_directory_or_failing_are_not_served() {
+    let dir = TempDir::new();
+    script(&dir, "cgi/fail.sh", "#!/bin/sh\nexit 1\n");
+    script(
+        &dir,
+        "outside.sh",
+        "#!/bin/sh\nprintf 'Content-Type: text/plain\\n\\nout'\n",
+    );
+    let cgi_dir = dir.path().join("cgi");
+    let server = TestServer::start(&["--cgi-dir", cgi_dir.to_str().unwrap()]);
+
+    let response = server.get("/cgi/..%2Foutside.sh");
+    assert_eq!(response.status, 404);
+    let response = server.get("/cgi/missing.sh");
+    assert_eq!(response.status, 404);
+    let response = server.get("/cgi/fail.sh");
+    assert_eq!(response.status, 502);
+}
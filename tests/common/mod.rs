@@ -0,0 +1,340 @@
+//! Helpers shared by the integration tests: a server on a port picked by the os and a
+//! minimal client reading the responses byte for byte, so that framing bugs are visible
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use http_server_starter_rust::config::Config;
+use http_server_starter_rust::{handlers, Result, Server, ServerBuilder};
+
+/// How long the client waits for the server before failing the test
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Configuration parsed as if given on the command line
+pub fn config(args: &[&str]) -> Config {
+    Config::from_args(args.iter().map(|x| x.to_string())).expect("Args should be valid")
+}
+
+/// A server running in its own thread, stopped when dropped
+pub struct TestServer {
+    pub addr: SocketAddr,
+    shutdown: Option<Sender<()>>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl TestServer {
+    /// Serves handlers::router() with the configuration given as command line args
+    pub fn start(args: &[&str]) -> Self {
+        Self::with_config(config(args))
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        let router = handlers::router().expect("Routes should be valid");
+        Self::with_builder(Server::builder().config(config).router(router))
+    }
+
+    /// The port of the builder is replaced by 0 so that tests can run in parallel
+    pub fn with_builder(builder: ServerBuilder) -> Self {
+        let server = builder.port(0).build().expect("Server should build");
+        let addr = server.local_addr().expect("Server should have an address");
+        let (shutdown, signal) = mpsc::channel();
+        let thread = thread::spawn(move || server.run_with_shutdown(signal));
+        Self {
+            addr,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn connect(&self) -> Client {
+        Client::connect(self.addr)
+    }
+
+    /// Sends a single GET request on a new connection
+    pub fn get(&self, path: &str) -> Response {
+        self.request(&format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ))
+    }
+
+    /// Sends a raw request on a new connection and reads one response
+    pub fn request(&self, raw: &str) -> Response {
+        self.connect().request(raw.as_bytes())
+    }
+
+    /// Waits for the server to stop by itself, for ex after POST /_shutdown
+    pub fn join(mut self) -> Result<()> {
+        self.thread
+            .take()
+            .expect("Server thread should be running")
+            .join()
+            .expect("Server thread should not panic")
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        drop(self.shutdown.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A connection to the server, several requests can be sent on it
+pub struct Client {
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    pub fn connect(addr: SocketAddr) -> Self {
+        let stream = TcpStream::connect(addr).expect("Server should accept connections");
+        stream
+            .set_read_timeout(Some(CLIENT_TIMEOUT))
+            .expect("Timeout should be valid");
+        Self {
+            reader: BufReader::new(stream),
+        }
+    }
+
+    pub fn send(&mut self, raw: &[u8]) {
+        self.reader
+            .get_mut()
+            .write_all(raw)
+            .expect("Request should be sent");
+    }
+
+    pub fn request(&mut self, raw: &[u8]) -> Response {
+        self.send(raw);
+        self.read_response()
+    }
+
+    pub fn read_response(&mut self) -> Response {
+        Response::read_from(&mut self.reader, false)
+    }
+
+    /// Response to a HEAD request, whose headers describe a body that is not sent
+    pub fn read_head_response(&mut self) -> Response {
+        Response::read_from(&mut self.reader, true)
+    }
+
+    /// Next line of a body read as it is sent, for ex server-sent events, without the
+    /// line ending
+    pub fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader
+            .read_line(&mut line)
+            .expect("Line should be readable");
+        line.trim_end_matches(['\r', '\n']).to_string()
+    }
+
+    /// Everything the server sends until it closes the connection
+    pub fn read_to_end(&mut self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.reader
+            .read_to_end(&mut bytes)
+            .expect("Connection should be readable");
+        bytes
+    }
+
+    /// True if the server closed the connection, either cleanly or with a reset
+    pub fn is_closed(&mut self) -> bool {
+        matches!(self.reader.fill_buf().map(|x| x.len()), Ok(0) | Err(_))
+    }
+
+    pub fn shutdown_write(&self) {
+        self.reader
+            .get_ref()
+            .shutdown(Shutdown::Write)
+            .expect("Connection should be open");
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        self.reader.get_ref()
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    /// for ex HTTP/1.1 200 OK
+    pub status_line: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// only filled for a chunked body
+    pub trailers: Vec<(String, String)>,
+}
+
+impl Response {
+    /// Reads one response, the body is delimited by its framing: Content-Length,
+    /// chunked or the end of the connection
+    pub fn read_from<R: BufRead>(reader: &mut R, head: bool) -> Self {
+        // NOTE: interim responses such as 100 Continue are returned like the others
+        let status_line = read_line(reader);
+        let status = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_else(|| panic!("Invalid status line {:?}", status_line));
+        let headers = read_fields(reader);
+        let mut response = Self {
+            status,
+            status_line,
+            headers,
+            body: Vec::new(),
+            trailers: Vec::new(),
+        };
+        if head || status < 200 || status == 204 || status == 304 {
+            return response;
+        }
+
+        let chunked = response
+            .header("transfer-encoding")
+            .is_some_and(|x| x.eq_ignore_ascii_case("chunked"));
+        let content_length = response.header("content-length").map(|x| {
+            x.parse::<usize>()
+                .expect("Content-Length should be a number")
+        });
+        match (chunked, content_length) {
+            (true, _) => {
+                loop {
+                    let size = read_line(reader);
+                    let size = usize::from_str_radix(size.split(';').next().unwrap(), 16)
+                        .expect("Chunk size should be hexadecimal");
+                    if size == 0 {
+                        break;
+                    }
+                    let mut chunk = vec![0; size];
+                    reader.read_exact(&mut chunk).expect("Chunk should be sent");
+                    response.body.extend(chunk);
+                    assert_eq!(read_line(reader), "", "Chunk should end with CRLF");
+                }
+                response.trailers = read_fields(reader);
+            }
+            (false, Some(length)) => {
+                response.body = vec![0; length];
+                reader
+                    .read_exact(&mut response.body)
+                    .expect("Body should be sent");
+            }
+            (false, None) => {
+                reader
+                    .read_to_end(&mut response.body)
+                    .expect("Body should be sent");
+            }
+        }
+        response
+    }
+
+    /// First value of the header, the name is case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Values of all the headers with this name
+    pub fn header_all(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Line without its CRLF, fails if the connection closes before the end of the line
+fn read_line<R: BufRead>(reader: &mut R) -> String {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .expect("Response should be readable");
+    line.strip_suffix("\r\n")
+        .unwrap_or_else(|| panic!("Line should end with CRLF: {:?}", line))
+        .to_string()
+}
+
+fn read_fields<R: BufRead>(reader: &mut R) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    loop {
+        let line = read_line(reader);
+        if line.is_empty() {
+            return fields;
+        }
+        let (key, value) = line
+            .split_once(':')
+            .unwrap_or_else(|| panic!("Invalid header {:?}", line));
+        fields.push((key.to_string(), value.trim().to_string()));
+    }
+}
+
+/// Directory removed when dropped, each one has a unique name so tests can run in
+/// parallel
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "http-server-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).expect("Temporary directory should be created");
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Path as a command line arg
+    pub fn arg(&self) -> &str {
+        self.0.to_str().expect("Temporary path should be utf8")
+    }
+
+    pub fn write(&self, name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).expect("Parent directory should be created");
+        }
+        std::fs::write(&path, content).expect("File should be written");
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn gzip(bytes: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).expect("Gzip should not fail");
+    encoder.finish().expect("Gzip should not fail")
+}
+
+pub fn gunzip(bytes: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(bytes)
+        .read_to_end(&mut decoded)
+        .expect("Body should be gzip");
+    decoded
+}
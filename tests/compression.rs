@@ -0,0 +1,185 @@
+mod common;
+
+use common::{gunzip, Response, TempDir, TestServer};
+
+#[test]
+fn response_is_compressed_only_when_the_client_accepts_gzip() {
+    let server = TestServer::start(&[]);
+    let message = "compressible".repeat(20);
+
+    let response = server.get(&format!("/echo/{}", message));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.text(), message);
+
+    let response = server.request(&format!(
+        "GET /echo/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nAccept-Encoding: gzip\r\n\r\n",
+        message
+    ));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+    assert_eq!(gunzip(&response.body), message.as_bytes());
+}
+
+#[test]
+fn unsupported_encoding_is_ignored() {
+    let server = TestServer::start(&[]);
+
+    let response = server.request(
+        "GET /echo/abc HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nAccept-Encoding: invalid-encoding\r\n\r\n",
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.text(), "abc");
+}
+
+#[test]
+fn encoding_priority_decides_between_equally_accepted_codings() {
+    let message = "compressible".repeat(20);
+    let request = format!(
+        "GET /echo/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nAccept-Encoding: gzip, deflate\r\n\r\n",
+        message
+    );
+
+    let server = TestServer::start(&["--encoding-priority", "deflate,gzip"]);
+    assert_eq!(
+        server.request(&request).header("content-encoding"),
+        Some("deflate")
+    );
+
+    let server = TestServer::start(&["--encoding-priority", "gzip,deflate"]);
+    assert_eq!(
+        server.request(&request).header("content-encoding"),
+        Some("gzip")
+    );
+}
+
+#[test]
+fn png_is_not_gzipped_unless_its_type_is_configured_compressible() {
+    let dir = TempDir::new();
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+    // NOTE: above the stream threshold, smaller files are raw bytes never compressed
+    png.resize(2 * 1024 * 1024, 0);
+    std::fs::write(dir.path().join("image.png"), &png).unwrap();
+    let request = "GET /files/image.png HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nAccept-Encoding: gzip\r\n\r\n";
+
+    let server = TestServer::start(&["--directory", dir.arg(), "--sniff-content-type"]);
+    let response = server.request(request);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("image/png"));
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.body, png);
+
+    let server = TestServer::start(&[
+        "--directory",
+        dir.arg(),
+        "--sniff-content-type",
+        "--compressible-types",
+        "text/*, image/png",
+    ]);
+    let response = server.request(request);
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+    assert_eq!(gunzip(&response.body), png);
+}
+
+#[test]
+fn vary_is_sent_whenever_the_response_could_have_been_compressed() {
+    let dir = TempDir::new();
+    dir.write("data.bin", "raw");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    let message = "compressible".repeat(20);
+    let vary = |response: &Response| {
+        response
+            .header_all("vary")
+            .iter()
+            .flat_map(|x| x.split(','))
+            .any(|x| x.trim().eq_ignore_ascii_case("accept-encoding"))
+    };
+
+    let response = server.request(&format!(
+        "GET /echo/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nAccept-Encoding: gzip\r\n\r\n",
+        message
+    ));
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+    assert!(vary(&response));
+
+    // NOTE: a cache must not serve this uncompressed body to a client accepting gzip
+    let response = server.get(&format!("/echo/{}", message));
+    assert_eq!(response.header("content-encoding"), None);
+    assert!(vary(&response));
+
+    // NOTE: an octet-stream is never compressed so it does not vary
+    let response = server.request(
+        "GET /files/data.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nAccept-Encoding: gzip\r\n\r\n",
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), None);
+    assert!(!vary(&response));
+}
+
+#[test]
+fn compression_is_skipped_for_a_body_it_does_not_shrink() {
+    let request = |message: &str| {
+        format!(
+            "GET /echo/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nAccept-Encoding: gzip\r\n\r\n",
+            message
+        )
+    };
+    // NOTE: level 0 stores the body as is, the gzip framing makes it bigger
+    let server = TestServer::start(&["--compression-level", "0"]);
+
+    let message = "a".repeat(2000);
+    let response = server.request(&request(&message));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.text(), message);
+    // NOTE: the client accepting gzip could still get it
+    assert!(response.header("vary").is_some());
+
+    // NOTE: the gzip framing alone is bigger than a small incompressible body
+    let server = TestServer::start(&[]);
+    let response = server.request(&request("x7Qk2"));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), None);
+    assert_eq!(response.body, b"x7Qk2");
+    assert_eq!(response.header("content-length"), Some("5"));
+    assert!(response.header("vary").is_some());
+}
+
+#[test]
+fn gzipped_echo_is_framed_by_its_exact_length_under_keep_alive() {
+    let server = TestServer::start(&[]);
+    let mut client = server.connect();
+    let messages = [
+        &"compressible".repeat(10),
+        &"compressible".repeat(200),
+        &"last".repeat(10),
+    ];
+
+    // NOTE: pipelined, a wrong Content-Length makes the next response start at the
+    // wrong offset
+    let mut raw = String::new();
+    for message in messages {
+        raw += &format!(
+            "GET /echo/{} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n",
+            message
+        );
+    }
+    raw += "GET /echo/plain HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    client.send(raw.as_bytes());
+
+    for message in messages {
+        let response = client.read_response();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("content-encoding"), Some("gzip"));
+        assert_eq!(response.header("transfer-encoding"), None);
+        assert_eq!(
+            response.header("content-length"),
+            Some(response.body.len().to_string().as_str())
+        );
+        assert_eq!(gunzip(&response.body), message.as_bytes());
+    }
+    let response = client.read_response();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "plain");
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn debug_echo_returns_the_raw_request() {
+    let server = TestServer::start(&[]);
+    let request = "POST /_debug/echo?b=2&a=1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nX-Custom: some value\r\nX-Other: 42\r\nContent-Length: 4\r\n\r\nbody";
+
+    let response = server.request(request);
+    assert_eq!(response.status, 200);
+    // NOTE: query parameters are sorted, their order is not kept
+    assert_eq!(response.text(), request.replace("?b=2&a=1", "?a=1&b=2"));
+}
+
+#[test]
+fn large_response_is_complete_with_the_smallest_write_buffer() {
+    let server = TestServer::start(&["--write-buffer-size", "1024"]);
+    let message = "a".repeat(5000);
+
+    let response = server.get(&format!("/echo/{}", message));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), message);
+}
+
+#[test]
+fn root_returns_the_root_file_when_configured() {
+    let dir = common::TempDir::new();
+    let root_file = dir.write("home.html", "<h1>home</h1>");
+    let server = TestServer::start(&["--root-file", root_file.to_str().unwrap()]);
+
+    let response = server.get("/");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("text/html"));
+    assert_eq!(response.text(), "<h1>home</h1>");
+}
+
+#[test]
+fn root_is_empty_by_default() {
+    let server = TestServer::start(&[]);
+
+    let response = server.get("/");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-length"), Some("0"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn head_has_the_headers_of_get_without_body() {
+    let server = TestServer::start(&[]);
+    let mut client = server.connect();
+
+    let get = client.request(b"GET /echo/hello HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    client.send(b"HEAD /echo/hello HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let head = client.read_head_response();
+    assert_eq!(head.status, 200);
+    assert_eq!(head.header("content-length"), get.header("content-length"));
+    assert_eq!(head.header("content-type"), get.header("content-type"));
+
+    // NOTE: a body after the HEAD response would be read as the next status line
+    let response =
+        client.request(b"GET /echo/next HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(response.text(), "next");
+}
+
+#[test]
+fn echo_representation_follows_the_accept_header() {
+    let server = TestServer::start(&[]);
+    let cases = [
+        (
+            "application/json",
+            "application/json",
+            r#"{"message":"hi"}"#,
+        ),
+        ("text/plain", "text/plain", "hi"),
+        ("*/*", "text/plain", "hi"),
+        ("application/*", "application/json", r#"{"message":"hi"}"#),
+        ("application/json;q=0.5, text/plain", "text/plain", "hi"),
+        (
+            "text/plain;q=0, application/json",
+            "application/json",
+            r#"{"message":"hi"}"#,
+        ),
+    ];
+
+    for (accept, content_type, body) in cases {
+        let response = server.request(&format!(
+            "GET /echo/hi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nAccept: {}\r\n\r\n",
+            accept
+        ));
+        assert_eq!(response.status, 200, "Accept: {}", accept);
+        assert_eq!(
+            response.header("content-type"),
+            Some(content_type),
+            "Accept: {}",
+            accept
+        );
+        assert_eq!(response.text(), body, "Accept: {}", accept);
+        assert!(response
+            .header("vary")
+            .is_some_and(|x| x.contains("Accept")));
+    }
+}
+
+#[test]
+fn landing_page_lists_the_routes_and_the_version() {
+    let server = TestServer::start(&["--landing-page"]);
+
+    let response = server.get("/");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("text/html"));
+    let page = response.text();
+    assert!(page.contains(&format!(
+        "http-server-starter-rust {}",
+        env!("CARGO_PKG_VERSION")
+    )));
+    for route in [
+        "GET /echo/*message",
+        "GET /files/*filename",
+        "POST /files/*filename",
+    ] {
+        assert!(
+            page.contains(&format!("<code>{}</code>", route)),
+            "{}",
+            route
+        );
+    }
+}
+
+#[test]
+fn bench_returns_exactly_the_requested_number_of_zeros() {
+    let server = TestServer::start(&[]);
+
+    for size in [0, 1, 1048576] {
+        let response = server.get(&format!("/_bench?size={}", size));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body.len(), size);
+        assert!(response.body.iter().all(|x| *x == 0));
+    }
+    // NOTE: the length is known in advance, an HTTP/1.0 response is not close-delimited
+    let response = server.request("GET /_bench?size=1000 HTTP/1.0\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.header("content-length"), Some("1000"));
+    assert_eq!(response.body.len(), 1000);
+
+    for query in ["", "?size=abc", "?size=-1", "?size=1073741825"] {
+        let response = server.get(&format!("/_bench{}", query));
+        assert_eq!(response.status, 400, "{}", query);
+    }
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{Client, TestServer};
+
+/// Opens an event stream over HTTP/1.0 so that the events are not chunked
+fn open_events(server: &TestServer) -> (Client, common::Response) {
+    let mut client = server.connect();
+    client.send(b"GET /events HTTP/1.0\r\nHost: localhost\r\n\r\n");
+    let response = client.read_head_response();
+    (client, response)
+}
+
+#[test]
+fn events_are_sent_until_the_client_disconnects() {
+    let server = TestServer::start(&[]);
+
+    let (mut client, response) = open_events(&server);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("text/event-stream"));
+    assert_eq!(response.header("content-length"), None);
+    for seconds in 0..2 {
+        assert_eq!(client.read_line(), format!("data: {}", seconds));
+        assert_eq!(client.read_line(), "");
+    }
+    drop(client);
+
+    assert_eq!(server.get("/echo/hi").status, 200);
+}
+
+#[test]
+fn event_streams_over_the_limit_get_a_503() {
+    // NOTE: the limit is kept below the 3 workers whatever the configured value
+    let server = TestServer::start(&["--threads", "3", "--max-event-streams", "10"]);
+
+    let (first, response) = open_events(&server);
+    assert_eq!(response.status, 200);
+    let (_second, response) = open_events(&server);
+    assert_eq!(response.status, 200);
+    let (_, response) = open_events(&server);
+    assert_eq!(response.status, 503);
+    assert!(response.header("retry-after").is_some());
+    // NOTE: the last worker still serves the other requests
+    assert_eq!(server.get("/echo/hi").status, 200);
+
+    // NOTE: the stream ends on the first event the closed connection fails to receive
+    drop(first);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let (_, response) = open_events(&server);
+        if response.status == 200 {
+            break;
+        }
+        assert!(Instant::now() < deadline, "Stream was never released");
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+#[test]
+fn event_streams_are_not_limited_by_threads_without_a_pool() {
+    // NOTE: threads is the size of a pool that does not exist
+    let server = TestServer::start(&["--mode", "thread-per-conn", "--threads", "1"]);
+    let streams: Vec<_> = (0..3).map(|_| open_events(&server)).collect();
+    for (_, response) in streams.iter() {
+        assert_eq!(response.status, 200);
+    }
+    assert_eq!(server.get("/echo/hi").status, 200);
+
+    let server = TestServer::start(&[
+        "--mode",
+        "thread-per-conn",
+        "--threads",
+        "1",
+        "--max-event-streams",
+        "2",
+    ]);
+    let (_first, response) = open_events(&server);
+    assert_eq!(response.status, 200);
+    let (_second, response) = open_events(&server);
+    assert_eq!(response.status, 200);
+    let (_, response) = open_events(&server);
+    assert_eq!(response.status, 503);
+}
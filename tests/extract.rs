@@ -0,0 +1,69 @@
+mod common;
+
+use common::{config, TestServer};
+use http_server_starter_rust::http::{HttpMethod, HttpResponse};
+use http_server_starter_rust::Server;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Greeting {
+    name: String,
+    count: u32,
+}
+
+/// Route answering with the fields extracted from the body
+fn server() -> TestServer {
+    TestServer::with_builder(Server::builder().config(config(&[])).route(
+        HttpMethod::Post,
+        "/greet",
+        |request, _config| {
+            let greeting: Greeting = request.extract()?;
+            Ok(HttpResponse::content_response(
+                &format!("{} x{}", greeting.name, greeting.count),
+                "text/plain",
+            ))
+        },
+    ))
+}
+
+fn post(server: &TestServer, content_type: &str, body: &str) -> common::Response {
+    server.request(&format!(
+        "POST /greet HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    ))
+}
+
+#[test]
+fn json_and_form_bodies_extract_into_the_same_struct() {
+    let server = server();
+
+    let response = post(&server, "application/json", r#"{"name":"ada","count":3}"#);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "ada x3");
+
+    let response = post(
+        &server,
+        "application/x-www-form-urlencoded; charset=utf-8",
+        "name=ada+lovelace&count=3",
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "ada lovelace x3");
+}
+
+#[test]
+fn unsupported_content_type_is_rejected_with_415() {
+    let server = server();
+
+    let response = post(&server, "text/plain", "name=ada&count=3");
+    assert_eq!(response.status, 415);
+}
+
+#[test]
+fn invalid_body_is_a_bad_request() {
+    let server = server();
+
+    let response = post(&server, "application/json", r#"{"name":"ada"}"#);
+    assert_eq!(response.status, 400);
+}
@@ -0,0 +1,773 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{config, gunzip, gzip, TempDir, TestServer};
+use http_server_starter_rust::http::{HttpMethod, HttpResponse};
+use http_server_starter_rust::{handlers, Server};
+
+#[test]
+fn download_query_param_sends_the_file_as_attachment() {
+    let dir = TempDir::new();
+    dir.write("sub/report.txt", "hello");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    let response = server.get("/files/sub/report.txt?download=1");
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("content-disposition"),
+        Some("attachment; filename=\"report.txt\"")
+    );
+    assert_eq!(response.text(), "hello");
+
+    let response = server.get("/files/sub/report.txt");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-disposition"), None);
+}
+
+#[test]
+fn download_is_named_after_the_served_file() {
+    let dir = TempDir::new();
+    dir.write("docs/index.html", "<h1>docs</h1>");
+    // NOTE: paths are not decoded, this file is the one served for its own name
+    dir.write("caf%C3%A9%22q%22.txt", "hello");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    let response = server.get("/files/docs/?download=1");
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("content-disposition"),
+        Some("attachment; filename=\"index.html\"")
+    );
+
+    let response = server.get("/files/caf%C3%A9%22q%22.txt?download=1");
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("content-disposition"),
+        Some("attachment; filename=\"caf_\\\"q\\\".txt\"; filename*=UTF-8''caf%C3%A9%22q%22.txt")
+    );
+
+    // NOTE: the directory is served without redirect, its name is not the file's
+    let server = TestServer::start(&["--directory", dir.arg(), "--strip-trailing-slash"]);
+    let response = server.get("/files/docs?download=1");
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("content-disposition"),
+        Some("attachment; filename=\"index.html\"")
+    );
+}
+
+#[test]
+fn directory_without_trailing_slash_is_redirected_to_its_index() {
+    let dir = TempDir::new();
+    dir.write("site/index.html", "<h1>index</h1>");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    let response = server.get("/files/site");
+    assert_eq!(response.status, 301);
+    assert_eq!(
+        response.header("location"),
+        Some("http://localhost/files/site/")
+    );
+
+    let response = server.get("/files/site/");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "<h1>index</h1>");
+}
+
+#[test]
+fn gzip_upload_is_stored_decompressed() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    let content = "compressed upload ".repeat(100);
+    let body = gzip(content.as_bytes());
+
+    let mut request = format!(
+        "POST /files/upload.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )
+    .into_bytes();
+    request.extend(&body);
+    let response = server.connect().request(&request);
+    assert_eq!(response.status, 201);
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("upload.txt")).unwrap(),
+        content
+    );
+}
+
+#[test]
+fn overwrite_is_refused_if_modified_since_the_given_date() {
+    let dir = TempDir::new();
+    dir.write("doc.txt", "original");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    let put = |date: &str| {
+        server.request(&format!(
+            "PUT /files/doc.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nIf-Unmodified-Since: {}\r\nContent-Length: 7\r\n\r\nupdated",
+            date
+        ))
+    };
+
+    // NOTE: the file was modified after this date
+    let response = put("Sun, 06 Nov 1994 08:49:37 GMT");
+    assert_eq!(response.status, 412);
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("doc.txt")).unwrap(),
+        "original"
+    );
+
+    let response = put("Fri, 01 Jan 2100 00:00:00 GMT");
+    assert_eq!(response.status, 204);
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("doc.txt")).unwrap(),
+        "updated"
+    );
+}
+
+#[test]
+fn large_upload_is_complete_with_small_and_large_read_buffers() {
+    let content: Vec<u8> = (0..1024 * 1024).map(|x| (x % 251) as u8).collect();
+    for size in ["1024", "65536"] {
+        let dir = TempDir::new();
+        let server = TestServer::start(&["--directory", dir.arg(), "--read-buffer-size", size]);
+
+        let mut request = format!(
+            "POST /files/large.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            content.len()
+        )
+        .into_bytes();
+        request.extend(&content);
+        let response = server.connect().request(&request);
+        assert_eq!(response.status, 201, "buffer size {}", size);
+        assert!(
+            std::fs::read(dir.path().join("large.bin")).unwrap() == content,
+            "buffer size {}",
+            size
+        );
+    }
+}
+
+#[test]
+fn large_chunked_upload_is_streamed_to_the_file() {
+    let content: Vec<u8> = (0..1024 * 1024).map(|x| (x % 251) as u8).collect();
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    let mut request = b"POST /files/chunked.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    for chunk in content.chunks(10_000) {
+        request.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+        request.extend(chunk);
+        request.extend(b"\r\n");
+    }
+    request.extend(b"0\r\n\r\n");
+    let response = server.connect().request(&request);
+    assert_eq!(response.status, 201);
+    assert!(std::fs::read(dir.path().join("chunked.bin")).unwrap() == content);
+}
+
+#[test]
+fn accept_ranges_differs_between_files_and_echo() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "hello");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    let response = server.get("/files/a.txt");
+    assert_eq!(response.header("accept-ranges"), Some("bytes"));
+
+    let response = server.get("/echo/hello");
+    assert_eq!(response.header("accept-ranges"), Some("none"));
+}
+
+#[test]
+fn unknown_extension_is_sniffed_when_enabled() {
+    let dir = TempDir::new();
+    dir.write("image", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR");
+
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    let response = server.get("/files/image");
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/octet-stream")
+    );
+
+    let server = TestServer::start(&["--directory", dir.arg(), "--sniff-content-type"]);
+    let response = server.get("/files/image");
+    assert_eq!(response.header("content-type"), Some("image/png"));
+}
+
+#[test]
+fn custom_route_takes_precedence_over_the_files_wildcard() {
+    let dir = TempDir::new();
+    dir.write("special", "from disk");
+    dir.write("other", "from disk");
+    let builder = Server::builder()
+        .config(config(&["--directory", dir.arg()]))
+        .router(handlers::router().unwrap())
+        .route(HttpMethod::Get, "/files/special", |_request, _config| {
+            Ok(HttpResponse::content_response("custom", "text/plain"))
+        });
+    let server = TestServer::with_builder(builder);
+
+    assert_eq!(server.get("/files/special").text(), "custom");
+    assert_eq!(server.get("/files/other").text(), "from disk");
+}
+
+#[test]
+fn index_response_has_the_content_location_of_the_index() {
+    let dir = TempDir::new();
+    dir.write("site/index.html", "<h1>index</h1>");
+    dir.write("site/page.html", "<h1>page</h1>");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    let response = server.get("/files/site/");
+    assert_eq!(
+        response.header("content-location"),
+        Some("http://localhost/files/site/index.html")
+    );
+
+    // NOTE: the served resource is the requested one
+    let response = server.get("/files/site/page.html");
+    assert_eq!(response.header("content-location"), None);
+}
+
+/// Content above the size from which files are streamed rather than read in memory
+fn large_text() -> Vec<u8> {
+    (0..3 * 1024 * 1024)
+        .map(|x| b"abcdefghij\n"[x % 11])
+        .collect()
+}
+
+#[test]
+fn large_file_keeps_its_content_length_and_the_connection() {
+    let content = large_text();
+    let dir = TempDir::new();
+    dir.write("large.txt", &content);
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    let mut client = server.connect();
+
+    let response = client.request(
+        b"GET /files/large.txt HTTP/1.0\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n",
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("content-length"),
+        Some(content.len().to_string().as_str())
+    );
+    assert!(response.body == content);
+
+    let response = client.request(
+        b"GET /files/large.txt HTTP/1.0\r\nHost: localhost\r\nConnection: keep-alive\r\nRange: bytes=1000-1999\r\n\r\n",
+    );
+    assert_eq!(response.status, 206);
+    assert_eq!(response.header("content-length"), Some("1000"));
+    assert!(response.body == content[1000..2000]);
+}
+
+#[test]
+fn large_file_is_gzipped_while_it_is_streamed() {
+    let content = large_text();
+    let dir = TempDir::new();
+    dir.write("large.txt", &content);
+    // NOTE: files are application/octet-stream, not compressed, unless sniffed
+    let server = TestServer::start(&["--directory", dir.arg(), "--sniff-content-type"]);
+
+    let response = server.request(
+        "GET /files/large.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nAccept-Encoding: gzip\r\n\r\n",
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-encoding"), Some("gzip"));
+    assert_eq!(response.header("transfer-encoding"), Some("chunked"));
+    assert!(response.body.len() < content.len());
+    assert!(gunzip(&response.body) == content);
+}
+
+#[test]
+fn binary_upload_is_stored_and_served_unchanged() {
+    let content: &[u8] = b"\x89PNG\r\n\x1a\n\xff\xfe\x00\xc3\x28\x80";
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    let mut request = format!(
+        "POST /files/image.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n\r\n",
+        content.len()
+    )
+    .into_bytes();
+    request.extend(content);
+    let response = server.connect().request(&request);
+    assert_eq!(response.status, 201);
+
+    let response = server.get("/files/image.bin");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, content);
+}
+
+#[test]
+fn range_with_another_unit_gets_the_full_file() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "0123456789");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    let response = server.request(
+        "GET /files/a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nRange: items=0-4\r\n\r\n",
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-range"), None);
+    assert_eq!(response.text(), "0123456789");
+
+    let response = server.request(
+        "GET /files/a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nRange: bytes=0-4\r\n\r\n",
+    );
+    assert_eq!(response.status, 206);
+    assert_eq!(response.header("content-range"), Some("bytes 0-4/10"));
+    assert_eq!(response.text(), "01234");
+}
+
+#[test]
+fn dotfiles_are_hidden_unless_enabled() {
+    let dir = TempDir::new();
+    dir.write(".env", "SECRET=1");
+    dir.write(".git/config", "[core]");
+    dir.write("public.txt", "public");
+
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    assert_eq!(server.get("/files/.env").status, 404);
+    // NOTE: a hidden parent directory hides everything below it
+    assert_eq!(server.get("/files/.git/config").status, 404);
+    assert_eq!(server.get("/files/public.txt").status, 200);
+    let response = server.request(
+        "POST /files/.env HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 3\r\n\r\nabc",
+    );
+    assert_eq!(response.status, 404);
+    assert_eq!(std::fs::read(dir.path().join(".env")).unwrap(), b"SECRET=1");
+
+    let server = TestServer::start(&["--directory", dir.arg(), "--serve-hidden"]);
+    assert_eq!(server.get("/files/.env").text(), "SECRET=1");
+    assert_eq!(server.get("/files/.git/config").text(), "[core]");
+}
+
+#[cfg(unix)]
+#[test]
+fn uploaded_file_gets_the_configured_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new();
+    for mode in ["0600", "0644"] {
+        let server = TestServer::start(&["--directory", dir.arg(), "--upload-mode", mode]);
+        let name = format!("upload-{}.txt", mode);
+
+        let response = server.request(&format!(
+            "POST /files/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 3\r\n\r\nabc",
+            name
+        ));
+        assert_eq!(response.status, 201);
+        let metadata = std::fs::metadata(dir.path().join(&name)).unwrap();
+        assert_eq!(
+            metadata.permissions().mode() & 0o7777,
+            u32::from_str_radix(mode, 8).unwrap()
+        );
+    }
+}
+
+#[test]
+fn delete_outcomes() {
+    let dir = TempDir::new();
+    dir.write("root/a.txt", "a");
+    let outside = dir.write("outside.txt", "outside");
+    let root = dir.path().join("root");
+    let server = TestServer::start(&["--directory", root.to_str().unwrap()]);
+    let delete = |path: &str| {
+        server.request(&format!(
+            "DELETE {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        ))
+    };
+
+    let response = delete("/files/a.txt");
+    assert_eq!(response.status, 204);
+    assert_eq!(response.header("content-length"), None);
+    assert!(response.body.is_empty());
+    assert!(!root.join("a.txt").exists());
+
+    assert_eq!(delete("/files/a.txt").status, 404);
+
+    assert_eq!(delete("/files/../outside.txt").status, 403);
+    assert!(outside.exists());
+}
+
+#[test]
+fn upload_over_the_per_client_limit_gets_a_429() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&[
+        "--directory",
+        dir.arg(),
+        "--threads",
+        "4",
+        "--max-uploads-per-client",
+        "2",
+    ]);
+    let start_upload = |name: &str| {
+        let mut client = server.connect();
+        client.send(
+            format!(
+                "POST /files/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 6\r\n\r\nabc",
+                name
+            )
+            .as_bytes(),
+        );
+        client
+    };
+
+    // NOTE: both uploads are in progress, waiting for the rest of their body
+    let mut first = start_upload("first.txt");
+    let mut second = start_upload("second.txt");
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let mut third = start_upload("third.txt");
+    third.send(b"def");
+    let response = third.read_response();
+    assert_eq!(response.status, 429);
+    assert!(response.header("retry-after").is_some());
+
+    first.send(b"def");
+    assert_eq!(first.read_response().status, 201);
+    second.send(b"def");
+    assert_eq!(second.read_response().status, 201);
+    let mut fourth = start_upload("fourth.txt");
+    fourth.send(b"def");
+    assert_eq!(fourth.read_response().status, 201);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_out_of_the_root_is_forbidden_unless_followed() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new();
+    let secret = dir.write("outside/secret.txt", "secret");
+    let root = dir.path().join("root");
+    std::fs::create_dir(&root).unwrap();
+    symlink(&secret, root.join("link.txt")).unwrap();
+    symlink(dir.path().join("outside"), root.join("linked-dir")).unwrap();
+
+    let server = TestServer::start(&["--directory", root.to_str().unwrap()]);
+    assert_eq!(server.get("/files/link.txt").status, 403);
+    // NOTE: every component is checked, not only the file itself
+    assert_eq!(server.get("/files/linked-dir/secret.txt").status, 403);
+    let response = server.request(
+        "POST /files/linked-dir/new.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 3\r\n\r\nabc",
+    );
+    assert_eq!(response.status, 403);
+    assert!(!dir.path().join("outside/new.txt").exists());
+
+    let server = TestServer::start(&["--directory", root.to_str().unwrap(), "--follow-symlinks"]);
+    assert_eq!(server.get("/files/link.txt").text(), "secret");
+    assert_eq!(server.get("/files/linked-dir/secret.txt").text(), "secret");
+}
+
+#[test]
+fn upload_creates_with_201_and_location_then_overwrites_with_204() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    let upload = |content: &str| {
+        server.request(&format!(
+            "POST /files/notes.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        ))
+    };
+
+    let response = upload("first");
+    assert_eq!(response.status, 201);
+    assert_eq!(
+        response.header("location"),
+        Some("http://localhost/files/notes.txt")
+    );
+
+    let response = upload("second");
+    assert_eq!(response.status, 204);
+    assert_eq!(response.header("location"), None);
+    assert_eq!(
+        std::fs::read(dir.path().join("notes.txt")).unwrap(),
+        b"second"
+    );
+}
+
+#[test]
+fn cache_headers_follow_the_max_age() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "a");
+
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    let response = server.get("/files/a.txt");
+    assert_eq!(response.header("cache-control"), None);
+    assert_eq!(response.header("expires"), None);
+
+    let server = TestServer::start(&["--directory", dir.arg(), "--cache-max-age", "3600"]);
+    let response = server.get("/files/a.txt");
+    assert_eq!(
+        response.header("cache-control"),
+        Some("public, max-age=3600")
+    );
+    let expires = response.header("expires").unwrap();
+    assert!(expires.ends_with(" GMT"), "{}", expires);
+    assert_ne!(Some(expires), response.header("date"));
+
+    let server = TestServer::start(&["--directory", dir.arg(), "--cache-max-age", "0"]);
+    let response = server.get("/files/a.txt");
+    assert_eq!(response.header("cache-control"), Some("no-store"));
+    assert_eq!(response.header("expires"), None);
+}
+
+#[test]
+fn verbose_upload_is_complete() {
+    let content: Vec<u8> = (0..3 * 1024 * 1024).map(|x| (x % 251) as u8).collect();
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg(), "--verbose"]);
+
+    let mut request = format!(
+        "POST /files/large.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+        content.len()
+    )
+    .into_bytes();
+    request.extend(&content);
+    assert_eq!(server.connect().request(&request).status, 201);
+    assert!(std::fs::read(dir.path().join("large.bin")).unwrap() == content);
+}
+
+#[test]
+fn verbose_upload_reports_its_progress_up_to_the_body_length() {
+    let content: Vec<u8> = (0..3 * 1024 * 1024).map(|x| (x % 251) as u8).collect();
+    let dir = TempDir::new();
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let mut config = config(&[
+        "--directory",
+        dir.arg(),
+        "--verbose",
+        "--progress-interval",
+        "65536",
+    ]);
+    let reported = progress.clone();
+    config.on_progress = Some(Arc::new(move |read| reported.lock().unwrap().push(read)));
+    let server = TestServer::with_config(config);
+
+    let mut request = format!(
+        "POST /files/large.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+        content.len()
+    )
+    .into_bytes();
+    request.extend(&content);
+    assert_eq!(server.connect().request(&request).status, 201);
+
+    let progress = progress.lock().unwrap();
+    assert!(progress.len() > 1, "{:?}", progress);
+    assert!(progress.windows(2).all(|x| x[0] <= x[1]), "{:?}", progress);
+    assert_eq!(progress.last(), Some(&(content.len() as u64)));
+}
+
+#[test]
+fn method_override_routes_a_post_as_delete_only_when_allowed() {
+    let dir = TempDir::new();
+    let path = dir.write("a.txt", "hello");
+    let override_request = "POST /files/a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nX-HTTP-Method-Override: DELETE\r\nContent-Length: 0\r\n\r\n";
+
+    // NOTE: without the flag this is a plain upload, rejected for its missing body
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    assert_eq!(server.request(override_request).status, 400);
+    assert!(path.is_file());
+
+    let server = TestServer::start(&["--directory", dir.arg(), "--allow-method-override"]);
+    assert_eq!(server.request(override_request).status, 204);
+    assert!(!path.is_file());
+
+    dir.write("a.txt", "hello");
+    let response = server.request(
+        "POST /files/a.txt?_method=DELETE HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+    );
+    assert_eq!(response.status, 204);
+    assert!(!path.is_file());
+
+    // NOTE: overriding to a safe method is refused
+    let response = server.request(
+        "POST /files/a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nX-HTTP-Method-Override: GET\r\nContent-Length: 0\r\n\r\n",
+    );
+    assert_eq!(response.status, 400);
+    assert!(!path.is_file());
+}
+
+#[test]
+fn redirect_location_is_built_from_the_request_host() {
+    let dir = TempDir::new();
+    dir.write("site/index.html", "<h1>index</h1>");
+    let request = "GET /files/site HTTP/1.1\r\nHost: example.com:8080\r\nConnection: close\r\nX-Forwarded-Proto: https\r\nX-Forwarded-Host: public.example.com, proxy.internal\r\n\r\n";
+    let location = |args: &[&str]| {
+        let mut args = args.to_vec();
+        args.extend(["--directory", dir.arg()]);
+        let response = TestServer::start(&args).request(request);
+        assert_eq!(response.status, 301);
+        response.header("location").map(String::from)
+    };
+
+    // NOTE: the forwarded headers are only trusted behind a proxy
+    assert_eq!(
+        location(&[]).as_deref(),
+        Some("http://example.com:8080/files/site/")
+    );
+    assert_eq!(
+        location(&["--behind-proxy"]).as_deref(),
+        Some("https://public.example.com/files/site/")
+    );
+    assert_eq!(
+        location(&["--behind-proxy", "--base-url", "https://files.example.com"]).as_deref(),
+        Some("https://files.example.com/files/site/")
+    );
+
+    // NOTE: an invalid host is not echoed back, the client resolves a relative Location
+    let response = TestServer::start(&["--directory", dir.arg()]).request(
+        "GET /files/site HTTP/1.1\r\nHost: evil.com/<script>\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(response.header("location"), Some("/files/site/"));
+}
+
+#[test]
+fn interrupted_upload_leaves_neither_a_partial_file_nor_a_temporary_one() {
+    let dir = TempDir::new();
+    dir.write("kept.txt", "previous");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    for name in ["new.txt", "kept.txt"] {
+        let mut client = server.connect();
+        client.send(
+            format!(
+                "POST /files/{} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\n{}",
+                name,
+                "a".repeat(50)
+            )
+            .as_bytes(),
+        );
+        // NOTE: the body stops in the middle of the write
+        client.shutdown_write();
+        client.read_to_end();
+    }
+    // NOTE: a chunked body failing after its first chunk
+    let mut client = server.connect();
+    client.send(
+        b"POST /files/new.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\nzz\r\n",
+    );
+    assert_eq!(client.read_response().status, 400);
+
+    let mut names: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|x| x.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["kept.txt"]);
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("kept.txt")).unwrap(),
+        "previous"
+    );
+}
+
+#[test]
+fn stacked_content_codings_are_all_decoded() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    let upload = |name: &str, encoding_headers: &str, body: &[u8]| {
+        let mut request = format!(
+            "POST /files/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n",
+            name,
+            encoding_headers,
+            body.len()
+        )
+        .into_bytes();
+        request.extend(body);
+        server.connect().request(&request).status
+    };
+    let content = "doubly compressed ".repeat(100);
+
+    let body = gzip(&gzip(content.as_bytes()));
+    assert_eq!(
+        upload("list.txt", "Content-Encoding: gzip, gzip\r\n", &body),
+        201
+    );
+    // NOTE: a repeated header is the same list
+    let headers = "Content-Encoding: gzip\r\nContent-Encoding: gzip\r\n";
+    assert_eq!(upload("repeated.txt", headers, &body), 201);
+    for name in ["list.txt", "repeated.txt"] {
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(name)).unwrap(),
+            content
+        );
+    }
+
+    assert_eq!(
+        upload("br.txt", "Content-Encoding: gzip, br\r\n", &body),
+        415
+    );
+    assert!(!dir.path().join("br.txt").exists());
+}
+
+#[test]
+fn not_modified_sends_the_etag_of_the_representation_the_client_has() {
+    let dir = TempDir::new();
+    // NOTE: above the stream threshold, the only files that are compressed
+    let content = "compressible line\n".repeat(100_000);
+    dir.write("big.txt", &content);
+    let server = TestServer::start(&["--directory", dir.arg(), "--sniff-content-type"]);
+    let get = |headers: &str| {
+        server.request(&format!(
+            "GET /files/big.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n",
+            headers
+        ))
+    };
+
+    let identity = get("");
+    assert_eq!(identity.status, 200);
+    let strong = identity.header("etag").unwrap().to_string();
+    assert!(strong.starts_with('"'), "{}", strong);
+    let compressed = get("Accept-Encoding: gzip\r\n");
+    assert_eq!(compressed.header("content-encoding"), Some("gzip"));
+    let weak = compressed.header("etag").unwrap().to_string();
+    assert_eq!(weak, format!("W/{}", strong));
+
+    // NOTE: If-None-Match uses the weak comparison, either tag matches
+    for tag in [&strong, &weak] {
+        let response = get(&format!(
+            "If-None-Match: {}\r\nAccept-Encoding: gzip\r\n",
+            tag
+        ));
+        assert_eq!(response.status, 304, "{}", tag);
+        assert_eq!(response.header("etag"), Some(weak.as_str()));
+
+        let response = get(&format!("If-None-Match: {}\r\n", tag));
+        assert_eq!(response.status, 304, "{}", tag);
+        assert_eq!(response.header("etag"), Some(strong.as_str()));
+    }
+    let response = get("If-None-Match: \"other\", W/\"other\"\r\n");
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn if_match_uses_the_strong_comparison() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "hello");
+    let server = TestServer::start(&["--directory", dir.arg()]);
+    let strong = server
+        .get("/files/a.txt")
+        .header("etag")
+        .unwrap()
+        .to_string();
+    let delete = |if_match: &str| {
+        server
+            .request(&format!(
+                "DELETE /files/a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nIf-Match: {}\r\n\r\n",
+                if_match
+            ))
+            .status
+    };
+
+    // NOTE: a weak tag never matches strongly, even with the same value
+    assert_eq!(delete(&format!("W/{}", strong)), 412);
+    assert_eq!(delete("\"other\""), 412);
+    assert!(dir.path().join("a.txt").exists());
+    assert_eq!(delete(&format!("\"other\", {}", strong)), 204);
+    assert!(!dir.path().join("a.txt").exists());
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::{config, Response, TestServer};
+use http_server_starter_rust::http::{HttpMethod, HttpResponse, HttpStatus};
+use http_server_starter_rust::Server;
+
+#[test]
+fn security_headers_are_added_only_when_enabled() {
+    let server = TestServer::start(&[]);
+    let response = server.get("/echo/hi");
+    assert_eq!(response.header("x-content-type-options"), None);
+    assert_eq!(response.header("x-frame-options"), None);
+    assert_eq!(response.header("content-security-policy"), None);
+
+    let server = TestServer::start(&[
+        "--security-headers",
+        "--content-security-policy",
+        "default-src 'self'",
+    ]);
+    // NOTE: errors are responses too
+    for path in ["/echo/hi", "/missing"] {
+        let response = server.get(path);
+        assert_eq!(response.header("x-content-type-options"), Some("nosniff"));
+        assert_eq!(response.header("x-frame-options"), Some("DENY"));
+        assert_eq!(
+            response.header("content-security-policy"),
+            Some("default-src 'self'")
+        );
+    }
+}
+
+#[test]
+fn echo_headers_groups_duplicates_whatever_their_case() {
+    let server = TestServer::start(&[]);
+
+    let response = server.request(
+        "GET /_echo_headers HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nX-Tag: a\r\nx-tag: b\r\nX-TAG: c\r\nUser-Agent: test\r\n\r\n",
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("application/json"));
+    let headers: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(
+        headers,
+        serde_json::json!({
+            "host": "localhost",
+            "connection": "close",
+            "x-tag": ["a", "b", "c"],
+            "user-agent": "test",
+        })
+    );
+}
+
+#[test]
+fn server_timing_reports_a_duration_when_enabled() {
+    let server = TestServer::start(&[]);
+    assert_eq!(server.get("/echo/hi").header("server-timing"), None);
+
+    let server = TestServer::start(&["--server-timing"]);
+    let response = server.get("/echo/hi");
+    let duration = response
+        .header("server-timing")
+        .and_then(|x| x.strip_prefix("total;dur="))
+        .and_then(|x| x.parse::<f64>().ok())
+        .unwrap_or_else(|| panic!("Invalid Server-Timing {:?}", response.headers));
+    assert!(duration >= 0.0);
+}
+
+#[test]
+fn extra_headers_are_all_added_without_clobbering_by_default() {
+    let server = TestServer::start(&[
+        "--add-header",
+        "X-Powered-By: me",
+        "--add-header",
+        "X-Team: core",
+        "--add-header",
+        "Content-Type: text/html",
+    ]);
+    for path in ["/echo/hi", "/missing"] {
+        let response = server.get(path);
+        assert_eq!(response.header("x-powered-by"), Some("me"));
+        assert_eq!(response.header("x-team"), Some("core"));
+    }
+    let response = server.get("/echo/hi");
+    assert_eq!(response.header_all("content-type"), vec!["text/plain"]);
+
+    let server = TestServer::start(&[
+        "--override-headers",
+        "--add-header",
+        "Content-Type: text/html",
+    ]);
+    let response = server.get("/echo/hi");
+    assert_eq!(response.header_all("content-type"), vec!["text/html"]);
+}
+
+#[test]
+fn oversized_response_headers_are_replaced_by_a_500() {
+    let builder = Server::builder()
+        .config(config(&["--max-response-headers-size", "1024"]))
+        .route(HttpMethod::Get, "/header/*size", |request, _config| {
+            let size = request.params["size"].parse().unwrap();
+            let mut response = HttpResponse::content_response("body", "text/plain");
+            response.set_header("X-Big", &"a".repeat(size));
+            Ok(response)
+        });
+    let server = TestServer::with_builder(builder);
+
+    let response = server.get("/header/100");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("x-big").map(str::len), Some(100));
+
+    let response = server.get("/header/2000");
+    assert_eq!(response.status, 500);
+    assert_eq!(response.header("x-big"), None);
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn header_names_are_canonical_only_when_enabled() {
+    let builder = |args: &[&str]| {
+        Server::builder().config(config(args)).route(
+            HttpMethod::Get,
+            "/lowercase",
+            |_request, _config| {
+                let mut response = HttpResponse::new(HttpStatus::Ok200);
+                response.set_header("content-length", "0");
+                response.set_header("etag", "\"v1\"");
+                Ok(response)
+            },
+        )
+    };
+    let names = |response: &Response| {
+        response
+            .headers
+            .iter()
+            .map(|(key, _)| key.clone())
+            .filter(|key| ["content-length", "etag"].contains(&key.to_lowercase().as_str()))
+            .collect::<Vec<_>>()
+    };
+
+    let server = TestServer::with_builder(builder(&[]));
+    assert_eq!(names(&server.get("/lowercase")), ["content-length", "etag"]);
+
+    let server = TestServer::with_builder(builder(&["--canonical-header-names"]));
+    assert_eq!(names(&server.get("/lowercase")), ["Content-Length", "ETag"]);
+}
@@ -0,0 +1,427 @@
+mod common;
+
+use std::io::Write;
+
+use common::{TempDir, TestServer};
+
+#[test]
+fn oversized_body_with_expect_is_rejected_before_it_is_sent() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg(), "--max-body-size", "10"]);
+    let mut client = server.connect();
+
+    // NOTE: the body is never sent, a 100 Continue would leave the client waiting
+    client.send(
+        b"POST /files/big.txt HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 11\r\n\r\n",
+    );
+    let response = client.read_response();
+    assert_eq!(response.status, 413);
+    assert_eq!(response.header("x-max-body-size"), Some("10"));
+    assert!(!dir.path().join("big.txt").exists());
+}
+
+#[test]
+fn path_is_normalized_before_routing() {
+    let server = TestServer::start(&["--merge-slashes", "--strip-trailing-slash"]);
+
+    let response = server.get("//echo///hi");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "hi");
+
+    let response = server.get("/echo/hi/");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "hi");
+}
+
+#[test]
+fn oversized_header_line_is_rejected_with_431() {
+    let server = TestServer::start(&["--max-header-line-length", "100"]);
+
+    let response = server.request(&format!(
+        "GET /echo/hi HTTP/1.1\r\nHost: localhost\r\nX-Big: {}\r\n\r\n",
+        "a".repeat(200)
+    ));
+    assert_eq!(response.status, 431);
+
+    let response = server.request(&format!(
+        "GET /echo/hi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nX-Small: {}\r\n\r\n",
+        "a".repeat(50)
+    ));
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn oversized_request_line_is_rejected_with_414() {
+    let server = TestServer::start(&["--max-request-line-length", "100"]);
+    let mut client = server.connect();
+
+    // NOTE: the line never ends, the server must not wait for the rest of it
+    client.send(format!("GET /echo/{}", "a".repeat(10_000)).as_bytes());
+    let response = client.read_response();
+    assert_eq!(response.status, 414);
+
+    let response = server.request(&format!(
+        "GET /echo/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        "a".repeat(50)
+    ));
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn absolute_form_target_is_routed_by_its_path() {
+    let server = TestServer::start(&[]);
+
+    let response = server.request(
+        "GET http://localhost/echo/absolute HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "absolute");
+}
+
+#[test]
+fn target_form_reserved_to_another_method_is_rejected() {
+    let server = TestServer::start(&[]);
+
+    let response = server.request("GET * HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 400);
+    let response = server.request("GET localhost:80 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn over_deep_path_is_rejected_with_414() {
+    let server = TestServer::start(&["--max-path-depth", "4"]);
+
+    let response = server.get("/echo/a/b");
+    assert_eq!(response.status, 200);
+    let response = server.get("/echo/a/b/c/d");
+    assert_eq!(response.status, 414);
+    // NOTE: counted once percent decoded, an encoded slash is a separator for files
+    let response = server.get("/echo/a%2Fb%2Fc%2Fd");
+    assert_eq!(response.status, 414);
+    // NOTE: counted once the slashes are merged
+    let server = TestServer::start(&["--max-path-depth", "4", "--merge-slashes"]);
+    let response = server.get("/echo//a//b");
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn default_path_depth_is_64() {
+    let server = TestServer::start(&[]);
+
+    let response = server.get(&format!("/echo{}", "/a".repeat(63)));
+    assert_eq!(response.status, 200);
+    let response = server.get(&format!("/echo{}", "/a".repeat(64)));
+    assert_eq!(response.status, 414);
+}
+
+#[test]
+fn payload_too_large_tells_the_configured_limit() {
+    let server = TestServer::start(&["--max-body-size", "1024"]);
+
+    let body = "a".repeat(2000);
+    let response = server.request(&format!(
+        "POST /_debug/echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    assert_eq!(response.status, 413);
+    assert_eq!(response.header("x-max-body-size"), Some("1024"));
+    assert_eq!(
+        response.header("content-length"),
+        Some(response.body.len().to_string().as_str())
+    );
+    assert_eq!(
+        response.text(),
+        "Request body exceeds the maximum size of 1024 bytes"
+    );
+}
+
+#[test]
+fn te_with_trailers_is_accepted_and_an_unknown_coding_rejected() {
+    let server = TestServer::start(&[]);
+
+    let response = server.request(
+        "GET /echo/te HTTP/1.1\r\nHost: localhost\r\nConnection: close, TE\r\nTE: trailers\r\n\r\n",
+    );
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "te");
+
+    let response = server.request(
+        "GET /echo/te HTTP/1.1\r\nHost: localhost\r\nConnection: close, TE\r\nTE: bogus\r\n\r\n",
+    );
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn chunked_body_over_the_limit_is_rejected_before_the_chunk_is_sent() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg(), "--max-body-size", "100"]);
+    let head =
+        "POST /files/big.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+    // NOTE: only the size of the chunk is sent, the server must not wait for its data
+    let mut client = server.connect();
+    client.send(format!("{}1000\r\n", head).as_bytes());
+    let response = client.read_response();
+    assert_eq!(response.status, 413);
+
+    // NOTE: the sizes are added, each chunk alone is below the limit
+    let mut client = server.connect();
+    client.send(format!("{}3c\r\n{}\r\n3c\r\n", head, "a".repeat(60)).as_bytes());
+    let response = client.read_response();
+    assert_eq!(response.status, 413);
+    assert!(!dir.path().join("big.txt").exists());
+}
+
+#[test]
+fn headers_and_trailers_over_the_count_limit_are_rejected_with_431() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg(), "--max-headers", "3"]);
+
+    let response =
+        server.request("GET /echo/hi HTTP/1.1\r\nHost: localhost\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n");
+    assert_eq!(response.status, 431);
+
+    let head = "POST /files/trailers.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n";
+    let response = server.request(&format!("{}A: 1\r\nB: 2\r\nC: 3\r\nD: 4\r\n\r\n", head));
+    assert_eq!(response.status, 431);
+    assert!(!dir.path().join("trailers.txt").exists());
+
+    let response = server.request(&format!("{}A: 1\r\nB: 2\r\nC: 3\r\n\r\n", head));
+    assert_eq!(response.status, 201);
+    assert_eq!(
+        std::fs::read(dir.path().join("trailers.txt")).unwrap(),
+        b"hi"
+    );
+}
+
+#[test]
+fn oversized_trailer_line_is_rejected_with_431() {
+    let server = TestServer::start(&["--max-header-line-length", "100"]);
+    let mut client = server.connect();
+
+    // NOTE: the line never ends, the server must not wait for the rest of it
+    client.send(
+        format!(
+            "POST /files/big.txt HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\nX-Big: {}",
+            "a".repeat(200)
+        )
+        .as_bytes(),
+    );
+    let response = client.read_response();
+    assert_eq!(response.status, 431);
+}
+
+#[test]
+fn too_many_query_params_or_too_long_query_is_rejected() {
+    let server = TestServer::start(&[]);
+
+    let query: Vec<String> = (0..100).map(|i| format!("a{}=1", i)).collect();
+    assert_eq!(
+        server.get(&format!("/echo/q?{}", query.join("&"))).status,
+        200
+    );
+    let query: Vec<String> = (0..101).map(|i| format!("a{}=1", i)).collect();
+    assert_eq!(
+        server.get(&format!("/echo/q?{}", query.join("&"))).status,
+        400
+    );
+    // NOTE: a repeated key still counts, even if only the last value is kept
+    assert_eq!(
+        server
+            .get(&format!("/echo/q?{}", "a=1&".repeat(101)))
+            .status,
+        400
+    );
+
+    let server = TestServer::start(&["--max-query-length", "16"]);
+    assert_eq!(
+        server.get(&format!("/echo/q?a={}", "b".repeat(14))).status,
+        200
+    );
+    assert_eq!(
+        server.get(&format!("/echo/q?a={}", "b".repeat(15))).status,
+        414
+    );
+}
+
+#[test]
+fn connection_closed_before_any_request_gets_no_response() {
+    let server = TestServer::start(&[]);
+
+    let mut client = server.connect();
+    client.shutdown_write();
+    assert!(client.read_to_end().is_empty());
+
+    // NOTE: dropped without even a half close
+    drop(server.connect());
+
+    let response = server.get("/echo/hi");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "hi");
+}
+
+#[test]
+fn body_trickled_past_the_request_read_timeout_gets_a_408() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg(), "--request-read-timeout", "300ms"]);
+    let mut client = server.connect();
+
+    client.send(b"POST /files/slow.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n");
+    // NOTE: each byte comes well within 300ms of the previous one, only the total is late
+    for byte in b"0123456789" {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        if client.stream().write_all(&[*byte]).is_err() {
+            break;
+        }
+    }
+    let response = client.read_response();
+    assert_eq!(response.status, 408);
+    assert!(!dir.path().join("slow.txt").exists());
+
+    let response = server.request(
+        "POST /files/fast.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 10\r\n\r\n0123456789",
+    );
+    assert_eq!(response.status, 201);
+}
+
+#[test]
+fn malformed_requests_get_a_400() {
+    let server = TestServer::start(&[]);
+    let table = [
+        "\n",
+        "\r\n\r\n",
+        "GET\r\n\r\n",
+        "GET /echo/hi\r\n\r\n",
+        "GET  /echo/hi HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "GET /echo/hi  HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "GET /echo/hi HTTP/1.1 extra\r\nHost: localhost\r\n\r\n",
+        "GET /echo/hi HTTP/1.1\nHost: localhost\r\n\r\n",
+        "GET /echo/hi HTTP/1.1\r\n:value\r\n\r\n",
+        "GET /echo/hi HTTP/1.1\r\nHost localhost\r\n\r\n",
+        "GET /echo/hi HTTP/1.1\r\n folded: header\r\n\r\n",
+    ];
+    for raw in table {
+        let mut client = server.connect();
+        client.send(raw.as_bytes());
+        client.shutdown_write();
+        let response = client.read_response();
+        assert_eq!(response.status, 400, "{:?}", raw);
+    }
+}
+
+#[test]
+fn content_length_past_u64_is_too_large_rather_than_invalid() {
+    let server = TestServer::start(&[]);
+    let max = u64::MAX.to_string();
+    for content_length in [max.as_str(), "18446744073709551616", &"9".repeat(100)] {
+        let response = server.request(&format!(
+            "POST /_debug/echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            content_length
+        ));
+        assert_eq!(response.status, 413, "Content-Length: {}", content_length);
+    }
+
+    // NOTE: a sign is still invalid, see InvalidContentLength
+    let response = server.request(
+        "POST /_debug/echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: -1\r\n\r\n",
+    );
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn content_length_that_is_not_a_bare_decimal_gets_a_400() {
+    let server = TestServer::start(&[]);
+    for content_length in ["+42", "4 2", "abc", "0x10", "-1", "", "42,42"] {
+        let response = server.request(&format!(
+            "POST /_debug/echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            content_length,
+            "a".repeat(42)
+        ));
+        assert_eq!(response.status, 400, "Content-Length: {:?}", content_length);
+    }
+
+    // NOTE: the whitespace around a header value is not part of it
+    let response = server.request(&format!(
+        "POST /_debug/echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length:  42 \r\n\r\n{}",
+        "a".repeat(42)
+    ));
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn non_ascii_request_line_gets_a_400() {
+    let server = TestServer::start(&[]);
+    // NOTE: invalid utf8, valid utf8 that is not ascii, and a high byte in the version
+    let table: [&[u8]; 3] = [
+        b"GET /echo/\xff HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "GET /echo/caf\u{e9} HTTP/1.1\r\nHost: localhost\r\n\r\n".as_bytes(),
+        b"GET /echo/hi HTTP/1.1\x80\r\nHost: localhost\r\n\r\n",
+    ];
+    for raw in table {
+        let mut client = server.connect();
+        let response = client.request(raw);
+        assert_eq!(response.status, 400, "{:?}", String::from_utf8_lossy(raw));
+    }
+
+    // NOTE: the same path percent-encoded is fine
+    assert_eq!(server.get("/echo/caf%C3%A9").status, 200);
+}
+
+#[test]
+fn request_rejected_whatever_its_body_gets_the_final_status_instead_of_100() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg(), "--deny", "POST:/files/private/"]);
+    let table = [
+        ("POST /files/private/a.txt", 403),
+        ("POST /echo/hi", 405),
+        ("POST /missing", 404),
+    ];
+    for (request_line, status) in table {
+        let mut client = server.connect();
+        // NOTE: the body is never sent, the client waits for 100 Continue
+        client.send(
+            format!(
+                "{} HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 3\r\n\r\n",
+                request_line
+            )
+            .as_bytes(),
+        );
+        let response = client.read_response();
+        assert_eq!(response.status, status, "{}", request_line);
+    }
+    assert!(!dir.path().join("private/a.txt").exists());
+
+    // NOTE: an accepted request gets its 100 Continue
+    let mut client = server.connect();
+    client.send(
+        b"POST /files/a.txt HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 3\r\n\r\n",
+    );
+    assert_eq!(client.read_head_response().status, 100);
+    client.send(b"abc");
+    assert_eq!(client.read_response().status, 201);
+}
+
+#[test]
+fn unknown_expectation_gets_a_417() {
+    let dir = TempDir::new();
+    let server = TestServer::start(&["--directory", dir.arg()]);
+
+    for expect in ["200-ok", "100-continue, 200-ok", "continue"] {
+        let response = server.request(&format!(
+            "POST /files/a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nExpect: {}\r\nContent-Length: 3\r\n\r\nabc",
+            expect
+        ));
+        assert_eq!(response.status, 417, "Expect: {}", expect);
+    }
+    assert!(!dir.path().join("a.txt").exists());
+
+    // NOTE: the expectation is case insensitive
+    let mut client = server.connect();
+    client.send(
+        b"POST /files/a.txt HTTP/1.1\r\nHost: localhost\r\nExpect: 100-Continue\r\nContent-Length: 3\r\n\r\nabc",
+    );
+    assert_eq!(client.read_head_response().status, 100);
+    assert_eq!(client.read_response().status, 201);
+}
@@ -0,0 +1,411 @@
+mod common;
+
+use std::{io, sync::mpsc, thread, time::Duration};
+
+use common::{config, Client, Response, TempDir, TestServer};
+use http_server_starter_rust::http::{HttpHeader, HttpMethod, HttpResponse};
+use http_server_starter_rust::{buffer_pool, handlers, HttpError, Server};
+
+#[test]
+fn server_with_custom_backlog_accepts_connections() {
+    let server = TestServer::start(&["--backlog", "16"]);
+
+    let response = server.get("/echo/backlog");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "backlog");
+}
+
+#[test]
+fn both_concurrency_modes_serve_requests() {
+    for mode in ["pool", "thread-per-conn"] {
+        let server = TestServer::start(&["--mode", mode]);
+
+        let response = server.get("/echo/mode");
+        assert_eq!(response.status, 200, "mode {}", mode);
+        assert_eq!(response.text(), "mode", "mode {}", mode);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_serves_requests() {
+    use std::io::{BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let dir = TempDir::new();
+    // NOTE: a stale socket file left by a previous run must not prevent the bind
+    let socket = dir.path().join("http.sock");
+    drop(UnixListener::bind(&socket).unwrap());
+    let server = Server::builder()
+        .config(config(&["--unix-socket", socket.to_str().unwrap()]))
+        .router(handlers::router().unwrap())
+        .build()
+        .unwrap();
+    let (shutdown, signal) = mpsc::channel();
+    let thread = thread::spawn(move || server.run_with_shutdown(signal));
+
+    let mut stream = UnixStream::connect(&socket).unwrap();
+    stream
+        .write_all(b"GET /echo/unix HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let response = Response::read_from(&mut BufReader::new(stream), false);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "unix");
+
+    drop(shutdown);
+    thread.join().unwrap().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket_does_not_replace_a_regular_file() {
+    let dir = TempDir::new();
+    let path = dir.write("data.txt", "keep me");
+
+    let built = Server::builder()
+        .config(config(&["--unix-socket", path.to_str().unwrap()]))
+        .build();
+    assert!(built.is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), b"keep me");
+}
+
+#[test]
+fn internal_error_body_contains_a_unique_error_id() {
+    let builder = Server::builder().config(config(&[])).route(
+        HttpMethod::Get,
+        "/fail",
+        |_request, _config| Err(io::Error::other("disk on fire"))?,
+    );
+    let server = TestServer::with_builder(builder);
+
+    let ids: Vec<String> = (0..2)
+        .map(|_| {
+            let response = server.get("/fail");
+            assert_eq!(response.status, 500);
+            let text = response.text();
+            // NOTE: the cause is only logged, not sent to the client
+            assert!(!text.contains("disk on fire"));
+            let id = text
+                .strip_prefix("Internal error: ")
+                .unwrap_or_else(|| panic!("Unexpected body {:?}", text));
+            assert!(!id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit() || c == '-'));
+            id.to_string()
+        })
+        .collect();
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[test]
+fn handler_past_the_request_deadline_gets_a_503() {
+    let builder = Server::builder()
+        .config(config(&["--request-timeout", "100ms"]))
+        .route(HttpMethod::Get, "/slow", |_request, _config| {
+            thread::sleep(Duration::from_millis(300));
+            Ok(HttpResponse::content_response("too late", "text/plain"))
+        })
+        .route(HttpMethod::Get, "/fast", |_request, _config| {
+            Ok(HttpResponse::content_response("in time", "text/plain"))
+        });
+    let server = TestServer::with_builder(builder);
+
+    let response = server.get("/slow");
+    assert_eq!(response.status, 503);
+    assert_eq!(response.text(), "");
+
+    let response = server.get("/fast");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "in time");
+}
+
+#[test]
+fn builder_serves_its_routes_until_shutdown() {
+    let dir = TempDir::new();
+    dir.write("a.txt", "from the builder");
+    let server = Server::builder()
+        .directory(dir.arg())
+        .threads(2)
+        .port(0)
+        .router(handlers::router().unwrap())
+        .route(HttpMethod::Get, "/custom", |_request, _config| {
+            Ok(HttpResponse::content_response("custom", "text/plain"))
+        })
+        .build()
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let (shutdown, signal) = mpsc::channel();
+    let thread = thread::spawn(move || server.run_with_shutdown(signal));
+
+    let response = Client::connect(addr)
+        .request(b"GET /files/a.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(response.text(), "from the builder");
+    let response = Client::connect(addr)
+        .request(b"GET /custom HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(response.text(), "custom");
+
+    drop(shutdown);
+    thread.join().unwrap().unwrap();
+}
+
+#[test]
+fn invalid_route_is_reported_by_build() {
+    let built = Server::builder()
+        .port(0)
+        .route(HttpMethod::Get, "/files/*rest/more", |_request, _config| {
+            Ok(HttpResponse::no_content())
+        })
+        .build();
+    assert!(built.is_err());
+}
+
+#[test]
+fn port_0_is_replaced_by_the_port_picked_by_the_os() {
+    let server = Server::builder()
+        .config(config(&[]))
+        .port(0)
+        .build()
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    assert_ne!(addr.port(), 0);
+    assert!(addr.ip().is_loopback());
+
+    // NOTE: the socket listens as soon as it is built, before run is called
+    std::net::TcpStream::connect(addr).unwrap();
+}
+
+#[test]
+fn body_of_unknown_length_is_close_delimited_for_http_1_0() {
+    let content: Vec<u8> = (0..100_000).map(|x| (x % 251) as u8).collect();
+    let body = content.clone();
+    let builder = Server::builder().config(config(&[])).route(
+        HttpMethod::Get,
+        "/stream",
+        move |_request, _config| {
+            Ok(HttpResponse::stream_response(
+                io::Cursor::new(body.clone()),
+                "application/octet-stream",
+            ))
+        },
+    );
+    let server = TestServer::with_builder(builder);
+
+    // NOTE: even when asked to keep the connection alive, its end is the end of the body
+    let mut client = server.connect();
+    let response = client
+        .request(b"GET /stream HTTP/1.0\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-length"), None);
+    assert_eq!(response.header("transfer-encoding"), None);
+    assert!(response.body == content);
+    assert!(client.is_closed());
+
+    // NOTE: http/1.1 clients get a chunked body instead
+    let response = server.get("/stream");
+    assert_eq!(response.header("transfer-encoding"), Some("chunked"));
+    assert!(response.body == content);
+}
+
+#[test]
+fn panicking_handler_gets_a_500_and_the_connection_survives() {
+    let builder = Server::builder()
+        .config(config(&[]))
+        .route(HttpMethod::Get, "/panic", |_request, _config| {
+            panic!("handler bug")
+        })
+        .route(HttpMethod::Get, "/fine", |_request, _config| {
+            Ok(HttpResponse::content_response("fine", "text/plain"))
+        });
+    let server = TestServer::with_builder(builder);
+    let mut client = server.connect();
+
+    let response = client.request(b"GET /panic HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 500);
+    assert!(response.text().starts_with("Internal error: "));
+    assert!(!response.text().contains("handler bug"));
+
+    let response = client.request(b"GET /fine HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "fine");
+}
+
+#[test]
+fn saturated_server_answers_503_with_retry_after() {
+    let server = TestServer::start(&["--max-connections", "1", "--retry-after", "7"]);
+    let mut busy = server.connect();
+    let response = busy.request(b"GET /echo/busy HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 200);
+
+    let response = server.get("/echo/refused");
+    assert_eq!(response.status, 503);
+    assert_eq!(response.header("retry-after"), Some("7"));
+
+    // NOTE: the slot is released once the server sees the connection closed
+    drop(busy);
+    let served = (0..50).any(|_| {
+        thread::sleep(Duration::from_millis(20));
+        server.get("/echo/served").status == 200
+    });
+    assert!(served);
+}
+
+#[test]
+fn connection_is_closed_after_the_pipelined_request_limit() {
+    let server = TestServer::start(&["--max-requests-per-connection", "3"]);
+    let mut client = server.connect();
+
+    client.send(&b"GET /echo/pipelined HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(5));
+    for i in 1..=3 {
+        let response = client.read_response();
+        assert_eq!(response.text(), "pipelined");
+        let closing = response.header("connection") == Some("close");
+        assert_eq!(closing, i == 3, "request {}", i);
+    }
+    assert!(client.is_closed());
+}
+
+#[test]
+fn idle_connection_is_closed_by_the_reaper() {
+    let server = TestServer::start(&["--idle-timeout", "200ms", "--keep-alive-timeout", "30s"]);
+    let mut client = server.connect();
+    let response = client.request(b"GET /echo/idle HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 200);
+
+    // NOTE: well before the keep-alive timeout, which would close it otherwise
+    let start = std::time::Instant::now();
+    assert!(client.is_closed());
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn failure_rate_1_fails_every_response_and_0_none() {
+    let server = TestServer::start(&["--fault-injection", "--inject-failure-rate", "1"]);
+    for _ in 0..10 {
+        assert_eq!(server.get("/echo/fault").status, 500);
+    }
+
+    let server = TestServer::start(&["--fault-injection", "--inject-failure-rate", "0"]);
+    for _ in 0..10 {
+        assert_eq!(server.get("/echo/fault").status, 200);
+    }
+
+    // NOTE: ignored without the master flag
+    let server = TestServer::start(&["--inject-failure-rate", "1"]);
+    assert_eq!(server.get("/echo/fault").status, 200);
+}
+
+#[test]
+fn injected_latency_delays_the_response() {
+    let server = TestServer::start(&["--fault-injection", "--inject-latency", "200ms"]);
+
+    let start = std::time::Instant::now();
+    assert_eq!(server.get("/echo/slow").status, 200);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn declared_trailer_is_sent_after_the_last_chunk() {
+    let builder = Server::builder().config(config(&[])).route(
+        HttpMethod::Get,
+        "/checksum",
+        |_request, _config| {
+            let mut response =
+                HttpResponse::stream_response(io::Cursor::new(b"hello trailers"), "text/plain");
+            response.set_trailers(&["Checksum"], || {
+                vec![HttpHeader {
+                    key: "Checksum".to_string(),
+                    value: "1234abcd".to_string(),
+                }]
+            });
+            Ok(response)
+        },
+    );
+    let server = TestServer::with_builder(builder);
+
+    let response = server.request(
+        "GET /checksum HTTP/1.1\r\nHost: localhost\r\nConnection: close, TE\r\nTE: trailers\r\n\r\n",
+    );
+    assert_eq!(response.header("transfer-encoding"), Some("chunked"));
+    assert_eq!(response.header("trailer"), Some("Checksum"));
+    assert_eq!(response.text(), "hello trailers");
+    assert_eq!(
+        response.trailers,
+        vec![("Checksum".to_string(), "1234abcd".to_string())]
+    );
+
+    // NOTE: without chunked framing, there is nowhere to put them
+    let response = server.request("GET /checksum HTTP/1.0\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.text(), "hello trailers");
+    assert!(response.trailers.is_empty());
+}
+
+#[test]
+fn handler_error_is_sent_with_its_status_and_message() {
+    let builder = Server::builder().config(config(&[])).route(
+        HttpMethod::Get,
+        "/validate",
+        |request, _config| {
+            let Some(name) = request.query.get("name") else {
+                Err(HttpError::bad_request("Missing name parameter"))?
+            };
+            Ok(HttpResponse::content_response(name, "text/plain"))
+        },
+    );
+    let server = TestServer::with_builder(builder);
+
+    let response = server.get("/validate");
+    assert_eq!(response.status, 400);
+    assert_eq!(response.header("content-type"), Some("text/plain"));
+    assert_eq!(response.text(), "Missing name parameter");
+
+    assert_eq!(server.get("/validate?name=ada").text(), "ada");
+}
+
+#[test]
+fn line_buffers_are_reused_across_requests() {
+    let server = TestServer::start(&[]);
+    assert_eq!(server.get("/echo/hi").status, 200);
+
+    let before = buffer_pool::hits();
+    assert_eq!(server.get("/echo/hi").status, 200);
+    assert!(buffer_pool::hits() > before);
+}
+
+#[test]
+fn same_routes_are_served_on_every_port() {
+    let server = Server::builder()
+        .config(config(&[]))
+        .router(handlers::router().unwrap())
+        .ports(&[0, 0])
+        .build()
+        .unwrap();
+    let addrs = server.local_addrs().unwrap();
+    assert_eq!(addrs.len(), 2);
+    assert_ne!(addrs[0].port(), addrs[1].port());
+    let (shutdown, signal) = mpsc::channel();
+    let thread = thread::spawn(move || server.run_with_shutdown(signal));
+
+    for addr in addrs {
+        let response = Client::connect(addr)
+            .request(b"GET /echo/hi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert_eq!(response.status, 200, "{}", addr);
+        assert_eq!(response.text(), "hi");
+    }
+
+    // NOTE: every accept loop stops on shutdown
+    drop(shutdown);
+    thread.join().unwrap().unwrap();
+}
+
+#[test]
+fn retry_after_can_be_sent_as_a_date() {
+    let server = TestServer::start(&["--max-connections", "1", "--retry-after-format", "date"]);
+    let mut busy = server.connect();
+    let response = busy.request(b"GET /echo/busy HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, 200);
+
+    let response = server.get("/echo/refused");
+    assert_eq!(response.status, 503);
+    // NOTE: IMF-fixdate, for ex Sun, 06 Nov 1994 08:49:37 GMT
+    let retry_after = response.header("retry-after").unwrap();
+    assert_eq!(retry_after.len(), 29, "{}", retry_after);
+    assert!(retry_after.ends_with(" GMT"), "{}", retry_after);
+}
@@ -0,0 +1,255 @@
+mod common;
+
+use std::{
+    io::{self, BufRead, Read, Write},
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{config, TestServer};
+use http_server_starter_rust::upgrade::UpgradeHandler;
+use http_server_starter_rust::{handlers, Server};
+
+/// Serves handlers::router() with /chat registered as a websocket route
+fn websocket_server(args: &[&str], upgrade: UpgradeHandler) -> TestServer {
+    let builder = Server::builder()
+        .config(config(args))
+        .router(handlers::router().unwrap())
+        .websocket("/chat", upgrade);
+    TestServer::with_builder(builder)
+}
+
+/// Handshake of the rfc example, with the method and version given
+fn handshake(method: &str, path: &str, version: Option<&str>) -> String {
+    let version = version
+        .map(|x| format!("Sec-WebSocket-Version: {}\r\n", x))
+        .unwrap_or_default();
+    format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n{}Content-Length: 0\r\n\r\n",
+        method, path, version
+    )
+}
+
+/// Upgrade handler recording whether it was called
+fn recording_handler() -> (UpgradeHandler, Arc<AtomicBool>) {
+    let called = Arc::new(AtomicBool::new(false));
+    let handler_called = Arc::clone(&called);
+    let handler: UpgradeHandler = Arc::new(move |_| handler_called.store(true, Ordering::SeqCst));
+    (handler, called)
+}
+
+#[test]
+fn websocket_handshake_hands_the_connection_over() {
+    // NOTE: echoes one line, enough to check the connection was handed over
+    let server = websocket_server(
+        &[],
+        Arc::new(|mut reader| {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            reader.get_mut().write_all(line.as_bytes()).unwrap();
+        }),
+    );
+    let mut client = server.connect();
+
+    let response = client.request(handshake("GET", "/chat", Some("13")).as_bytes());
+    assert_eq!(response.status, 101);
+    assert_eq!(response.header("upgrade"), Some("websocket"));
+    assert_eq!(
+        response.header("sec-websocket-accept"),
+        Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+    );
+
+    client.send(b"not http anymore\n");
+    assert_eq!(client.read_to_end(), b"not http anymore\n");
+}
+
+#[test]
+fn websocket_handshake_without_key_is_rejected() {
+    let server = websocket_server(&[], Arc::new(|_| {}));
+
+    let response = server.request(
+        "GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\r\n",
+    );
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn websocket_handshake_of_another_version_gets_a_426() {
+    let (handler, called) = recording_handler();
+    let server = websocket_server(&[], handler);
+
+    for version in [None, Some("8")] {
+        let response = server.request(&handshake("GET", "/chat", version));
+        assert_eq!(response.status, 426);
+        assert_eq!(response.header("sec-websocket-version"), Some("13"));
+        assert_eq!(response.header("upgrade"), Some("websocket"));
+    }
+    // NOTE: without upgrade, the client is told to upgrade as well
+    assert_eq!(server.get("/chat").status, 426);
+    assert!(!called.load(Ordering::SeqCst));
+}
+
+#[test]
+fn websocket_upgrade_with_another_method_is_rejected() {
+    let (handler, called) = recording_handler();
+    let server = websocket_server(&[], handler);
+
+    let response = server.request(&handshake("POST", "/chat", Some("13")));
+    assert_eq!(response.status, 405);
+    assert_eq!(response.header("allow"), Some("GET"));
+    assert!(!called.load(Ordering::SeqCst));
+}
+
+#[test]
+fn websocket_upgrade_of_a_denied_path_is_forbidden() {
+    let (handler, called) = recording_handler();
+    let server = websocket_server(&["--deny", "GET:/chat"], handler);
+
+    let response = server.request(&handshake("GET", "/chat", Some("13")));
+    assert_eq!(response.status, 403);
+    assert!(!called.load(Ordering::SeqCst));
+}
+
+#[test]
+fn routes_that_do_not_opt_in_are_not_upgraded() {
+    let (handler, called) = recording_handler();
+    let server = websocket_server(&[], handler);
+
+    let response = server.request(&handshake("GET", "/echo/hi", Some("13")));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.text(), "hi");
+    let response = server.request(&handshake("GET", "/missing", Some("13")));
+    assert_eq!(response.status, 404);
+    assert!(!called.load(Ordering::SeqCst));
+}
+
+#[test]
+fn connect_tunnels_bytes_to_the_upstream() {
+    // NOTE: upstream echoes everything back until the tunnel closes its side
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let authority = upstream.local_addr().unwrap().to_string();
+    let echo = thread::spawn(move || {
+        let (stream, _) = upstream.accept().unwrap();
+        io::copy(&mut &stream, &mut &stream).unwrap();
+    });
+    let rule = format!("CONNECT:{}", authority);
+    let server = TestServer::start(&["--enable-connect", "--allow", &rule]);
+    let mut client = server.connect();
+
+    // NOTE: a 2xx to CONNECT has no body, the bytes that follow belong to the tunnel
+    client.send(
+        format!(
+            "CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n",
+            authority, authority
+        )
+        .as_bytes(),
+    );
+    let response = client.read_head_response();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-length"), None);
+
+    client.send(b"through the tunnel");
+    client.shutdown_write();
+    assert_eq!(client.read_to_end(), b"through the tunnel");
+    echo.join().unwrap();
+}
+
+#[test]
+fn connect_is_refused_unless_enabled() {
+    let server = TestServer::start(&[]);
+
+    let response = server.request("CONNECT localhost:80 HTTP/1.1\r\nHost: localhost:80\r\n\r\n");
+    assert_eq!(response.status, 405);
+}
+
+#[test]
+fn connect_to_an_unreachable_upstream_is_a_bad_gateway() {
+    // NOTE: the port is free once the listener is dropped, so the connection is refused
+    let authority = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let rule = format!("CONNECT:{}", authority);
+    let server = TestServer::start(&["--enable-connect", "--allow", &rule]);
+
+    let response = server.request(&format!(
+        "CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n",
+        authority, authority
+    ));
+    assert_eq!(response.status, 502);
+}
+
+#[test]
+fn connect_to_an_authority_not_allowed_is_forbidden() {
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let authority = upstream.local_addr().unwrap().to_string();
+    // NOTE: the port of the upstream is not the allowed one
+    let server = TestServer::start(&["--enable-connect", "--allow", "CONNECT:127.0.0.1:1"]);
+
+    let response = server.request(&format!(
+        "CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n",
+        authority, authority
+    ));
+    assert_eq!(response.status, 403);
+    // NOTE: without allow rule, no tunnel is allowed at all
+    let server = TestServer::start(&["--enable-connect"]);
+    let response = server.request(&format!(
+        "CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n",
+        authority, authority
+    ));
+    assert_eq!(response.status, 403);
+
+    upstream.set_nonblocking(true).unwrap();
+    assert!(
+        upstream.accept().is_err(),
+        "Upstream should not be contacted"
+    );
+}
+
+#[test]
+fn idle_tunnel_is_closed_after_the_timeout() {
+    // NOTE: upstream echoes but never sends anything by itself
+    let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+    let authority = upstream.local_addr().unwrap().to_string();
+    let echo = thread::spawn(move || {
+        let (stream, _) = upstream.accept().unwrap();
+        let _ = io::copy(&mut &stream, &mut &stream);
+    });
+    let rule = format!("CONNECT:{}", authority);
+    let server = TestServer::start(&[
+        "--enable-connect",
+        "--allow",
+        &rule,
+        "--tunnel-idle-timeout",
+        "300ms",
+    ]);
+    let mut client = server.connect();
+    client.send(
+        format!(
+            "CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n",
+            authority, authority
+        )
+        .as_bytes(),
+    );
+    assert_eq!(client.read_head_response().status, 200);
+
+    // NOTE: traffic in one direction keeps the tunnel open past the timeout
+    let mut echoed = [0; 4];
+    for _ in 0..4 {
+        thread::sleep(Duration::from_millis(150));
+        client.send(b"ping");
+        client.stream().read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"ping");
+    }
+
+    let idle = Instant::now();
+    assert_eq!(client.read_to_end(), b"");
+    assert!(idle.elapsed() >= Duration::from_millis(300));
+    echo.join().unwrap();
+}